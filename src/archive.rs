@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use flate2::read::GzDecoder;
+
+use crate::Error;
+
+/// Separator of an archive and the path of a member in it, e.g. `images.zip!/train/0.jpg`.
+pub const SEP: &str = "!/";
+
+/// Archives kept open by an `Opened`, e.g. the one of a walk, shared by the threads reading their members.
+static OPENED: OnceLock<Mutex<HashMap<PathBuf, Weak<Archive>>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+/// Reader of an archive, used by one thread at a time.
+enum Reader {
+    Zip(zip::ZipArchive<File>),
+    Tar(File),
+    /// Decompressed in stream, at the offset of the tar
+    TarGz(Box<GzDecoder<BufReader<File>>>, u64),
+}
+
+impl Reader {
+    fn open(path: &Path, kind: Kind) -> std::io::Result<Self> {
+        let f = File::open(path)?;
+        Ok(match kind {
+            Kind::Zip => Self::Zip(
+                zip::ZipArchive::new(f)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            ),
+            Kind::Tar => Self::Tar(f),
+            Kind::TarGz => Self::TarGz(Box::new(GzDecoder::new(BufReader::new(f))), 0),
+        })
+    }
+
+    /// Content of the member at `pos` (its index for a zip, its offset for a tar) of `size` bytes.
+    fn read(&mut self, pos: u64, size: u64) -> std::io::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(size as usize);
+        match self {
+            Self::Zip(x) => {
+                let mut y = x
+                    .by_index(pos as usize)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                y.read_to_end(&mut buf)?;
+            }
+            Self::Tar(f) => {
+                f.seek(SeekFrom::Start(pos))?;
+                (&mut *f).take(size).read_to_end(&mut buf)?;
+            }
+            Self::TarGz(r, offset) => {
+                std::io::copy(&mut (&mut *r).take(pos - *offset), &mut std::io::sink())?;
+                (&mut *r).take(size).read_to_end(&mut buf)?;
+                *offset = pos + buf.len() as u64;
+            }
+        }
+        if buf.len() as u64 != size {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(buf)
+    }
+}
+
+/// An archive indexed once, its members read without extracting.
+struct Archive {
+    path: PathBuf,
+    kind: Kind,
+    /// Index and size of each zip member, offset and size of each tar member
+    members: BTreeMap<String, (u64, u64)>,
+    /// Readers not in use, one more opened whenever none is free
+    readers: Mutex<Vec<Reader>>,
+}
+
+/// Path of a member relative to the archive, `/` separated, without `./` and `..`.
+fn normalize(name: &Path) -> String {
+    name.components()
+        .filter_map(|x| match x {
+            Component::Normal(x) => Some(x.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Archive {
+    fn open(path: &Path) -> Result<Self, Error> {
+        let invalid = |e: &dyn std::fmt::Display| Error::Archive {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        };
+        let name = path.to_string_lossy().to_lowercase();
+        let kind = match name.ends_with(".zip") {
+            true => Kind::Zip,
+            false if name.ends_with(".tar") => Kind::Tar,
+            false => Kind::TarGz,
+        };
+        let mut members = BTreeMap::new();
+        let reader = match kind {
+            Kind::Zip => {
+                let mut x = zip::ZipArchive::new(File::open(path)?).map_err(|e| invalid(&e))?;
+                for idx in 0..x.len() {
+                    let y = x.by_index_raw(idx).map_err(|e| invalid(&e))?;
+                    if y.is_file() {
+                        members.insert(normalize(Path::new(y.name())), (idx as u64, y.size()));
+                    }
+                }
+                Some(Reader::Zip(x))
+            }
+            Kind::Tar | Kind::TarGz => {
+                // one pass over the stream for a `.tar.gz`, nothing kept but the index
+                let f = File::open(path)?;
+                let r: Box<dyn Read> = match kind {
+                    Kind::Tar => Box::new(f),
+                    _ => Box::new(GzDecoder::new(BufReader::new(f))),
+                };
+                let mut x = tar::Archive::new(r);
+                for y in x.entries().map_err(|e| invalid(&e))? {
+                    let y = y.map_err(|e| invalid(&e))?;
+                    if y.header().entry_type().is_file() {
+                        let name = normalize(&y.path().map_err(|e| invalid(&e))?);
+                        members.insert(name, (y.raw_file_position(), y.size()));
+                    }
+                }
+                None
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            kind,
+            members,
+            readers: Mutex::new(reader.into_iter().collect()),
+        })
+    }
+
+    /// A free reader for the member at `pos`, for a `.tar.gz` the stream nearest before it.
+    fn reader(&self, pos: u64) -> std::io::Result<Reader> {
+        let mut xs = self.readers.lock().unwrap();
+        let idx = match self.kind {
+            Kind::TarGz => xs
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, x)| match x {
+                    Reader::TarGz(_, offset) if *offset <= pos => Some((idx, *offset)),
+                    _ => None,
+                })
+                .max_by_key(|x| x.1)
+                .map(|x| x.0),
+            _ => xs.len().checked_sub(1),
+        };
+        match idx {
+            Some(idx) => Ok(xs.swap_remove(idx)),
+            None => {
+                drop(xs);
+                Reader::open(&self.path, self.kind)
+            }
+        }
+    }
+
+    fn read(&self, name: &str) -> std::io::Result<Vec<u8>> {
+        let (pos, size) = match self.members.get(name) {
+            Some(x) => *x,
+            None => return Err(std::io::ErrorKind::NotFound.into()),
+        };
+        let mut reader = self.reader(pos)?;
+
+        // a reader failed to read is dropped
+        let buf = reader.read(pos, size)?;
+        self.readers.lock().unwrap().push(reader);
+        Ok(buf)
+    }
+}
+
+/// The archive at `path` (canonicalized), opened and indexed again unless kept open.
+fn opened(path: &Path) -> Result<Arc<Archive>, Error> {
+    let mut xs = OPENED.get_or_init(Default::default).lock().unwrap();
+    if let Some(x) = xs.get(path).and_then(Weak::upgrade) {
+        return Ok(x);
+    }
+    let x = Arc::new(Archive::open(path)?);
+    xs.retain(|_, x| x.strong_count() > 0);
+    xs.insert(path.to_path_buf(), Arc::downgrade(&x));
+    Ok(x)
+}
+
+/// An archive kept open with its readers while alive, e.g. by the `Walked` of it.
+/// Its members are read by path, opening the archive again for each once dropped.
+#[derive(Clone)]
+pub struct Opened(Arc<Archive>);
+
+impl std::fmt::Debug for Opened {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Opened").field(&self.0.path).finish()
+    }
+}
+
+impl Opened {
+    /// Paths of the files in the archive, in their order in it, the one a `.tar.gz` is read fastest.
+    pub fn members(&self) -> Vec<PathBuf> {
+        let mut xs: Vec<(&String, u64)> = self.0.members.iter().map(|(k, v)| (k, v.0)).collect();
+        xs.sort_by_key(|x| x.1);
+        xs.into_iter().map(|x| member(&self.0.path, x.0)).collect()
+    }
+}
+
+/// Open the archive, indexing its members.
+pub fn open<P: AsRef<Path>>(archive: P) -> Result<Opened, Error> {
+    Ok(Opened(opened(&archive.as_ref().canonicalize()?)?))
+}
+
+/// Open the archives of the members among `paths`, each once, skipping the ones failed to open.
+/// Kept open while the handles are, instead of being opened and indexed again for each member.
+pub fn open_all<P: AsRef<Path>>(paths: &[P]) -> Vec<Opened> {
+    let archives: std::collections::BTreeSet<PathBuf> =
+        paths.iter().filter_map(|p| split(p).map(|x| x.0)).collect();
+    archives.iter().filter_map(|p| open(p).ok()).collect()
+}
+
+/// Whether the file is a zip or a tar (`.tar`, `.tar.gz`, `.tgz`) archive, by its extension.
+pub fn is_archive<P: AsRef<Path>>(p: P) -> bool {
+    let name = p.as_ref().to_string_lossy().to_lowercase();
+    [".zip", ".tar", ".tar.gz", ".tgz"]
+        .iter()
+        .any(|x| name.ends_with(x))
+}
+
+/// The archive and the member of a path like `images.zip!/train/0.jpg`, the archive an existing file.
+pub fn split<P: AsRef<Path>>(p: P) -> Option<(PathBuf, String)> {
+    let s = p.as_ref().to_str()?;
+    s.match_indices(SEP)
+        .map(|(idx, _)| (&s[..idx], &s[idx + SEP.len()..]))
+        .find(|(archive, _)| is_archive(archive) && Path::new(archive).is_file())
+        .map(|(archive, name)| (PathBuf::from(archive), name.to_string()))
+}
+
+/// Whether the path is a member of an archive.
+pub fn is_member<P: AsRef<Path>>(p: P) -> bool {
+    split(p).is_some()
+}
+
+/// Path of the member `name` of `archive`.
+pub fn member<P: AsRef<Path>>(archive: P, name: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}{}", archive.as_ref().display(), SEP, name))
+}
+
+/// Content of a member.
+pub fn read<P: AsRef<Path>>(p: P) -> std::io::Result<Vec<u8>> {
+    let p = p.as_ref();
+    let (archive, name) = split(p).ok_or(std::io::ErrorKind::NotFound)?;
+    let x = opened(&archive.canonicalize()?).map_err(|e| match e {
+        Error::Io(e) => e,
+        e => std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()),
+    })?;
+    x.read(&name)
+}
+
+/// Size of a member.
+pub fn size<P: AsRef<Path>>(p: P) -> Result<u64, Error> {
+    let p = p.as_ref();
+    let (archive, name) = split(p).ok_or(Error::NotExist(p.to_path_buf()))?;
+    match opened(&archive.canonicalize()?)?.members.get(&name) {
+        Some(x) => Ok(x.1),
+        None => Err(Error::NotExist(p.to_path_buf())),
+    }
+}
+
+/// Whether the member is in the archive.
+pub fn contains<P: AsRef<Path>>(p: P) -> bool {
+    size(p).is_ok()
+}
+
+/// Image reader of a member, with the format given by its extension, like `image::io::Reader::open`.
+pub fn reader<P: AsRef<Path>>(p: P) -> std::io::Result<image::io::Reader<Cursor<Vec<u8>>>> {
+    let p = p.as_ref();
+    let mut reader = image::io::Reader::new(Cursor::new(read(p)?));
+    if let Ok(format) = image::ImageFormat::from_path(p) {
+        reader.set_format(format);
+    }
+    Ok(reader)
+}
+
+/// Write the content of a member to `dst`.
+pub fn extract<P: AsRef<Path>, Q: AsRef<Path>>(p: P, dst: Q) -> std::io::Result<()> {
+    std::fs::write(dst, read(p)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_members() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path().join("a!");
+        std::fs::create_dir_all(dir.join("y.zip"))?;
+        for name in ["x.zip", "x.tar.gz", "x.tgz"] {
+            std::fs::write(dir.join(name), "")?;
+        }
+
+        let (archive, name) = split(member(dir.join("x.zip"), "b/c.jpg")).unwrap();
+        assert_eq!((archive, name.as_str()), (dir.join("x.zip"), "b/c.jpg"));
+
+        // the first separator after an archive, the rest is the member
+        let (archive, name) = split(member(dir.join("x.tar.gz"), "b/y.zip!/c.jpg")).unwrap();
+        assert_eq!(archive, dir.join("x.tar.gz"));
+        assert_eq!(name, "b/y.zip!/c.jpg");
+
+        // only an existing file is an archive, not a folder mirroring one
+        assert!(split(member(dir.join("y.zip"), "c.jpg")).is_none());
+        assert!(split(member(dir.join("z.zip"), "c.jpg")).is_none());
+        assert!(split(dir.join("x.zip")).is_none());
+        assert!(split(member(tmp.path().join("a"), "x.zip")).is_none());
+        assert_eq!(
+            split(member(dir.join("x.tgz"), "b/c.jpg")).unwrap().1,
+            "b/c.jpg"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_names() {
+        assert_eq!(normalize(Path::new("./b/c.jpg")), "b/c.jpg");
+        assert_eq!(normalize(Path::new("/b//c.jpg")), "b/c.jpg");
+        assert_eq!(normalize(Path::new("../b/../c.jpg")), "b/c.jpg");
+        assert_eq!(normalize(Path::new("b/x.zip!/c.jpg")), "b/x.zip!/c.jpg");
+    }
+}
@@ -0,0 +1,333 @@
+use anyhow::Result;
+use image::DynamicImage;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tract_onnx::prelude::*;
+
+use crate::document::{Collection, Failed};
+use crate::nn::{per_channel, preprocess};
+use crate::{
+    archive, canonical, decode, is_json, log_source, par_map, print_json, walk_all, Error,
+    FilterArgs, LOGGER, SAVEOUT_DEPRECATED,
+};
+
+#[derive(clap::Parser, Debug)]
+pub struct Args {
+    /// Image or folder, repeatable for several, `-` to read the paths of the files from stdin
+    #[arg(short, long, required_unless_present = "list", conflicts_with = "list")]
+    input: Vec<String>,
+
+    #[arg(short, long)]
+    recursive: bool,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    model: CaptionArgs,
+
+    /// Write the captions to a JSONL manifest instead of sidecar files, required for archives
+    #[arg(long)]
+    jsonl: Option<String>,
+
+    /// Suffix of the sidecar files appended to the file names of the images, e.g. `a.jpg.caption.txt`,
+    /// so that `a.jpg` and `a.png` don't share one, distinct from the `.txt` label files of YOLO-style datasets
+    #[arg(long, default_value = ".caption.txt")]
+    suffix: String,
+
+    /// Overwrite the existing sidecar files
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Number of threads, 0 for all the cores
+    #[arg(short, long, default_value_t = 0)]
+    jobs: usize,
+}
+
+/// Options of the onnx encoder-decoder captioning model.
+#[derive(clap::Args, Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CaptionArgs {
+    /// Onnx image encoder of the captioning model, outputs the encoder hidden states
+    #[arg(long)]
+    pub encoder: PathBuf,
+
+    /// Onnx causal text decoder of the captioning model, outputs the logits
+    #[arg(long)]
+    pub decoder: PathBuf,
+
+    /// Tokenizer of the decoder, a `tokenizer.json` of HuggingFace tokenizers
+    #[arg(long)]
+    pub tokenizer: PathBuf,
+
+    /// Input size of the image encoder
+    #[arg(long, default_value_t = 224)]
+    pub image_size: u32,
+
+    /// Per-channel mean for normalization
+    #[arg(long, value_delimiter = ',', default_values_t = [0.5f32, 0.5, 0.5])]
+    pub mean: Vec<f32>,
+
+    /// Per-channel std for normalization
+    #[arg(long, value_delimiter = ',', default_values_t = [0.5f32, 0.5, 0.5])]
+    pub std: Vec<f32>,
+
+    /// Token the decoding starts with
+    #[arg(long, default_value_t = 50256)]
+    pub bos_token_id: i64,
+
+    /// Token the decoding stops at
+    #[arg(long, default_value_t = 50256)]
+    pub eos_token_id: i64,
+
+    /// Max tokens of a caption
+    #[arg(long, default_value_t = 32)]
+    pub max_length: usize,
+}
+
+/// One line of the JSONL manifest.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct Caption {
+    pub path: PathBuf,
+    pub caption: String,
+}
+
+/// Captioned images, for `--format json`.
+#[derive(serde::Serialize, Debug)]
+struct Document<'a> {
+    command: &'static str,
+    #[serde(flatten)]
+    collection: Collection<'a>,
+    counts: BTreeMap<&'static str, usize>,
+    /// The JSONL manifest written, if any
+    jsonl: Option<&'a str>,
+    /// Suffix of the sidecar files written, without `--jsonl`
+    suffix: Option<&'a String>,
+}
+
+/// Onnx encoder-decoder captioning model running on CPU, greedy decoding.
+pub struct Captioner {
+    encoder: TypedRunnableModel<TypedModel>,
+    decoder: TypedRunnableModel<TypedModel>,
+    decoder_inputs: Vec<String>,
+    tokenizer: tokenizers::Tokenizer,
+    size: u32,
+    mean: [f32; 3],
+    std: [f32; 3],
+    bos: i64,
+    eos: i64,
+    max_length: usize,
+}
+
+impl Captioner {
+    pub fn new(args: &CaptionArgs) -> Result<Self, Error> {
+        let (mean, std) = per_channel(&args.mean, &args.std)?;
+        let size = args.image_size;
+        let tokenizer = tokenizers::Tokenizer::from_file(&args.tokenizer)
+            .map_err(|e| Error::model(&args.tokenizer, e))?;
+
+        // dry run for the shape of the encoder hidden states
+        let (encoder, shape) = (|| {
+            let encoder = tract_onnx::onnx()
+                .model_for_path(&args.encoder)?
+                .with_input_fact(0, f32::fact([1, 3, size as usize, size as usize]).into())?
+                .into_optimized()?
+                .into_runnable()?;
+            let x = preprocess(&DynamicImage::new_rgb8(size, size), size, &mean, &std);
+            let shape = encoder.run(tvec!(x.into()))?[0].shape().to_vec();
+            if shape.len() != 3 {
+                anyhow::bail!("Expected to output [1, N, D], got {:?}", shape);
+            }
+            Ok((encoder, shape))
+        })()
+        .map_err(|e| Error::model(&args.encoder, e))?;
+
+        // the decoder runs on `max_length` tokens, padded after the current one
+        let l = args.max_length;
+        let (decoder, decoder_inputs) = (|| {
+            let mut decoder = tract_onnx::onnx().model_for_path(&args.decoder)?;
+            let mut decoder_inputs: Vec<String> = Vec::new();
+            for i in 0..decoder.inputs.len() {
+                let name = decoder.node(decoder.inputs[i].node).name.to_string();
+                let fact = if name.contains("hidden_states") {
+                    f32::fact(&shape)
+                } else if name.contains("encoder") && name.contains("mask") {
+                    i64::fact([1, shape[1]])
+                } else if name.contains("ids") || name.contains("mask") {
+                    i64::fact([1, l])
+                } else {
+                    anyhow::bail!("Unknown input of the decoder: {}", name);
+                };
+                decoder = decoder.with_input_fact(i, fact.into())?;
+                decoder_inputs.push(name);
+            }
+            Ok((decoder.into_optimized()?.into_runnable()?, decoder_inputs))
+        })()
+        .map_err(|e| Error::model(&args.decoder, e))?;
+
+        Ok(Self {
+            encoder,
+            decoder,
+            decoder_inputs,
+            tokenizer,
+            size,
+            mean,
+            std,
+            bos: args.bos_token_id,
+            eos: args.eos_token_id,
+            max_length: l,
+        })
+    }
+
+    pub fn caption(&self, img: &DynamicImage) -> Result<String> {
+        let x = preprocess(img, self.size, &self.mean, &self.std);
+        let hidden_states = self.encoder.run(tvec!(x.into()))?.remove(0);
+        let n = hidden_states.shape()[1];
+        let l = self.max_length;
+
+        // greedy decoding
+        let mut ids: Vec<i64> = vec![self.bos];
+        while ids.len() < l {
+            let mut padded = vec![self.eos; l];
+            padded[..ids.len()].copy_from_slice(&ids);
+            let mut mask = vec![0i64; l];
+            mask[..ids.len()].iter_mut().for_each(|x| *x = 1);
+            let mut xs: TVec<TValue> = tvec!();
+            for name in self.decoder_inputs.iter() {
+                if name.contains("hidden_states") {
+                    xs.push(hidden_states.clone());
+                } else if name.contains("encoder") && name.contains("mask") {
+                    xs.push(Tensor::from_shape(&[1, n], &vec![1i64; n])?.into());
+                } else if name.contains("ids") {
+                    xs.push(Tensor::from_shape(&[1, l], &padded)?.into());
+                } else {
+                    xs.push(Tensor::from_shape(&[1, l], &mask)?.into());
+                }
+            }
+            let ys = self.decoder.run(xs)?;
+            let logits = ys[0].to_array_view::<f32>()?;
+            let logits = logits.index_axis(tract_ndarray::Axis(0), 0);
+            let logits = logits.index_axis(tract_ndarray::Axis(0), ids.len() - 1);
+            let next = logits
+                .iter()
+                .enumerate()
+                .fold((0, f32::MIN), |a, (i, &v)| if v > a.1 { (i, v) } else { a })
+                .0 as i64;
+            if next == self.eos {
+                break;
+            }
+            ids.push(next);
+        }
+        let ids = ids[1..].iter().map(|&x| x as u32).collect::<Vec<_>>();
+        let caption = self
+            .tokenizer
+            .decode(&ids, true)
+            .map_err(anyhow::Error::msg)?;
+        Ok(caption.trim().to_string())
+    }
+}
+
+impl Args {
+    pub fn run(&self) -> Result<()> {
+        let mut walked = walk_all(&self.input, self.recursive, false, &self.filter)?;
+        log_source("Source", self.recursive, &self.filter, &walked)?;
+        let paths = std::mem::take(&mut walked.files);
+
+        // archives are never modified, so no sidecar files next to their members
+        if self.jsonl.is_none() {
+            if let Some(p) = paths.iter().find(|p| archive::is_member(p)) {
+                return Err(Error::Options(format!(
+                    "Use `--jsonl <FILE>` to caption the members of archives: {}",
+                    p.display()
+                ))
+                .into());
+            }
+        }
+
+        // skip the sidecar files themselves and the captioned
+        let sidecar = |p: &Path| sidecar(p, &self.suffix);
+        let (paths, v_skipped): (Vec<PathBuf>, Vec<PathBuf>) = paths
+            .into_iter()
+            .filter(|p| !p.to_string_lossy().ends_with(&self.suffix))
+            .partition(|p| self.jsonl.is_some() || self.overwrite || !sidecar(p).exists());
+
+        let captioner = Captioner::new(&self.model)?;
+        let ys = par_map(&paths, self.jobs, "Captioning", |p| match decode(p) {
+            Err(e) => Ok(Err(Error::decoding(p, e))),
+            Ok(img) => captioner.caption(&img).map(Ok),
+        })?;
+
+        // save
+        let mut jsonl = match &self.jsonl {
+            None => None,
+            Some(x) => Some(std::io::BufWriter::new(std::fs::File::create(x)?)),
+        };
+        let mut v_deprecated: Vec<(PathBuf, Error)> = Vec::new();
+        let mut cnt_captioned = 0;
+        for (path, y) in paths.iter().zip(ys) {
+            match y? {
+                Err(e) => v_deprecated.push((path.to_path_buf(), e)),
+                Ok(caption) => {
+                    cnt_captioned += 1;
+                    match jsonl.as_mut() {
+                        Some(f) => {
+                            let line = Caption {
+                                path: canonical(path)?,
+                                caption,
+                            };
+                            writeln!(f, "{}", serde_json::to_string(&line)?)?;
+                        }
+                        None => std::fs::write(sidecar(path), caption + "\n")?,
+                    }
+                }
+            }
+        }
+        if let Some(mut f) = jsonl {
+            f.flush()?;
+        }
+
+        // summary
+        LOGGER.success("Found", "", "");
+        LOGGER.success("", "Captioned", &format!("x{}", cnt_captioned));
+        if !v_skipped.is_empty() {
+            LOGGER.success("", "Skipped", &format!("x{}", v_skipped.len()));
+        }
+        LOGGER.success("", SAVEOUT_DEPRECATED, &format!("x{}", v_deprecated.len()));
+        match &self.jsonl {
+            Some(x) => LOGGER.success("Results saved to", x, ""),
+            None => LOGGER.success(
+                "Results saved to",
+                "Sidecar files",
+                &format!("<IMAGE>{}", self.suffix),
+            ),
+        }
+        if is_json() {
+            print_json(&Document {
+                command: "caption",
+                collection: Collection::new(
+                    &walked,
+                    self.recursive,
+                    v_deprecated
+                        .iter()
+                        .map(|(p, e)| Failed::new(p, Some(e)))
+                        .collect(),
+                ),
+                counts: BTreeMap::from([
+                    ("captioned", cnt_captioned),
+                    ("skipped", v_skipped.len()),
+                    ("deprecated", v_deprecated.len()),
+                ]),
+                jsonl: self.jsonl.as_deref(),
+                suffix: self.jsonl.is_none().then_some(&self.suffix),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// `<IMAGE>` with `suffix` appended, the extension kept.
+fn sidecar(p: &Path, suffix: &str) -> PathBuf {
+    let mut x = p.as_os_str().to_os_string();
+    x.push(suffix);
+    PathBuf::from(x)
+}
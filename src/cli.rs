@@ -6,16 +6,35 @@ pub enum Task {
     /// Images de-duplicating
     Dedup(crate::deduplicator::Args),
 
-    /// Image-Image supported. TODO: Text-Image
+    /// Image-Image and Text-Image retrival
     Retrive(crate::retrival::Args),
 
-    /// TODO
-    Caption,
+    /// Persistent retrival index
+    Index(crate::indexer::Args),
+
+    /// Images captioning with a local onnx model
+    Caption(crate::captioner::Args),
+
+    /// Execute a plan saved by `--dry-run`
+    Apply(crate::plan::Args),
+
+    /// Restore the files moved by `--mv` with the journal in the output folder
+    Undo(crate::journal::Args),
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
 }
 
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// `json` prints one document on stdout for every subcommand, without logs and progress bars
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
     #[command(subcommand)]
     pub task: Task,
 }
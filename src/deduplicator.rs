@@ -1,180 +1,1026 @@
+use crate::document::{Collection, Failed, Saved};
+use crate::indexer::{Entry, Manifest};
 use crate::{
-    build_pb, load_files, make_folders, src2dst, Method, LOGGER, SAVEOUT_CURATED,
-    SAVEOUT_DEPRECATED, SAVEOUT_DUPLICATED,
+    build_index, build_pb, canonical, checksum, decode, is_json, log_source, par_map, print_json,
+    stat, thresh_help, walk, walk_all, Error, Extractor, Feat, FilterArgs, KeepArgs, Method,
+    NnArgs, SaveArgs, LOGGER, SAVEOUT_CURATED, SAVEOUT_DEPRECATED, SAVEOUT_DUPLICATED,
+    SAVEOUT_IDENTICAL,
 };
 use anyhow::Result;
-use rayon::prelude::*;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use image::GenericImageView;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 #[derive(clap::Parser, Debug)]
 pub struct Args {
-    #[arg(short, long)]
-    input: String,
+    /// Image or folder, repeatable for several, `-` to read the paths of the files from stdin
+    #[arg(short, long, required_unless_present = "list", conflicts_with = "list")]
+    input: Vec<String>,
 
-    #[arg(short, long)]
-    output: Option<String>,
+    #[command(flatten)]
+    save: SaveArgs,
 
     #[arg(short, long)]
     recursive: bool,
 
-    #[arg(long)]
-    mv: bool,
+    #[command(flatten)]
+    filter: FilterArgs,
 
     #[arg(long, value_enum, default_value_t = Method::BlockHash)]
     method: Method,
 
-    /// The smaller this parameter is, the lower the tolerance
-    #[arg(long, default_value_t = 3.0f32)]
-    thresh: f32,
+    /// Bits of the perceptual hashes, 256 for blockhash and 64 for the others by default
+    #[arg(long)]
+    bits: Option<usize>,
+
+    #[command(flatten)]
+    nn: NnArgs,
+
+    #[arg(long, help = thresh_help())]
+    thresh: Option<f32>,
 
     #[arg(short, long)]
     show_deprecated: bool,
+
+    /// Group byte-identical files by size and checksum before decoding
+    #[arg(long)]
+    exact: bool,
+
+    /// Flag only the files matching a reference collection, a folder or a saved index, which is never modified
+    #[arg(long, value_name = "PATH", conflicts_with = "exact")]
+    reference: Option<PathBuf>,
+
+    #[command(flatten)]
+    keep: KeepArgs,
+
+    /// Show the duplicate groups, byte-identical ones of `--exact` included, with the kept and the removed of each
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Save the groups with their distances, dimensions and file sizes, as CSV if it ends with `.csv`, JSON otherwise
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Save the curated only, e.g. to extract the curated members of an archive
+    #[arg(long)]
+    curated_only: bool,
+
+    /// Number of threads, 0 for all the cores
+    #[arg(short, long, default_value_t = 0)]
+    jobs: usize,
 }
 
-impl Args {
-    pub fn run(&self) -> Result<()> {
-        let paths = load_files(&self.input, self.recursive, false, None)?;
-        let pb = build_pb(paths.len() as u64, "Deduplicating");
-        let mut maps_curated: HashMap<PathBuf, blockhash::Blockhash256> = HashMap::new();
-        let mut v_dups: Vec<PathBuf> = Vec::new();
-        let mut v_deps: Vec<PathBuf> = Vec::new();
-        for path in &paths {
-            pb.inc(1);
-            // try load
-            let img = match image::io::Reader::open(path) {
-                Err(_) => {
-                    v_deps.push(path.to_path_buf());
-                    continue;
+/// A file of a duplicate group.
+#[derive(Serialize, Debug)]
+pub struct Member {
+    pub path: PathBuf,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub size: u64,
+    /// Distance to the kept one
+    pub distance: f32,
+    /// Input the file came from, for more than one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Pair {
+    pub a: PathBuf,
+    pub b: PathBuf,
+    pub distance: f32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Group {
+    pub id: usize,
+    /// Byte-identical copies found by `exact`
+    pub exact: bool,
+    pub kept: Member,
+    pub removed: Vec<Member>,
+    /// Pairwise distances, listed with `pairs` only, for the JSON report
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub distances: Vec<Pair>,
+}
+
+/// Groups of more members list the distances to the kept one only, not all the pairs.
+const MAX_PAIRWISE: usize = 256;
+
+/// Classification of one file, with the id of its group if duplicated.
+#[derive(Serialize, Debug)]
+pub struct Status {
+    pub path: PathBuf,
+    pub status: &'static str,
+    pub group: Option<usize>,
+    /// Input the file came from, for more than one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+}
+
+/// Groups of duplicates and the classified files, returned by `deduplicate`.
+#[derive(Debug)]
+pub struct Deduplication {
+    pub thresh: f32,
+    /// Groups of similar images, then groups of byte-identical copies
+    pub groups: Vec<Group>,
+    pub files: Vec<Status>,
+    pub curated: Vec<PathBuf>,
+    pub duplicated: Vec<PathBuf>,
+    pub identical: Vec<PathBuf>,
+    /// Files failed to stat, read or decode, with the errors
+    pub deprecated: Vec<(PathBuf, Error)>,
+}
+
+#[derive(Serialize, Debug)]
+struct Document<'a> {
+    command: &'static str,
+    #[serde(flatten)]
+    collection: Collection<'a>,
+    counts: BTreeMap<&'static str, usize>,
+    files: &'a [Status],
+    /// Collection of `--reference`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reference: Option<PathBuf>,
+    #[serde(flatten)]
+    report: &'a Report<'a>,
+    #[serde(flatten)]
+    saved: Saved,
+}
+
+#[derive(Serialize, Debug)]
+struct Report<'a> {
+    method: Method,
+    thresh: f32,
+    groups: &'a [Group],
+}
+
+impl Report<'_> {
+    /// Whether the report at `path` is a CSV, by its extension, JSON otherwise.
+    fn is_csv(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("csv"))
+    }
+
+    /// One row per file for CSV, the pairwise distances are in JSON only.
+    fn save(&self, path: &Path) -> Result<()> {
+        let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+        if Self::is_csv(path) {
+            writeln!(f, "group,exact,role,path,width,height,size,distance,root")?;
+            for g in self.groups.iter() {
+                let rows = std::iter::once(("kept", &g.kept))
+                    .chain(g.removed.iter().map(|x| ("removed", x)));
+                for (role, x) in rows {
+                    writeln!(
+                        f,
+                        "{},{},{},\"{}\",{},{},{},{},\"{}\"",
+                        g.id,
+                        g.exact,
+                        role,
+                        x.path.display().to_string().replace('"', "\"\""),
+                        x.width.map_or(String::new(), |x| x.to_string()),
+                        x.height.map_or(String::new(), |x| x.to_string()),
+                        x.size,
+                        x.distance,
+                        x.root.as_ref().map_or(String::new(), |x| x
+                            .display()
+                            .to_string()
+                            .replace('"', "\"\"")),
+                    )?;
                 }
-                Ok(reader) => match reader.with_guessed_format() {
-                    Err(_) => {
-                        v_deps.push(path.to_path_buf());
-                        continue;
-                    }
-                    Ok(x) => match x.decode() {
-                        Err(_) => {
-                            v_deps.push(path.to_path_buf());
-                            continue;
-                        }
-                        Ok(x) => x,
-                    },
-                },
-            };
-            let feat = blockhash::blockhash256(&img);
-            let mut _v_dup: Vec<(u64, PathBuf, blockhash::Blockhash256)> = Vec::new();
-            maps_curated.iter().for_each(|(p, f)| {
-                if feat.distance(f) <= self.thresh as u32 {
-                    _v_dup.push((
-                        std::fs::metadata(p).unwrap().len(),
-                        p.to_path_buf(),
-                        f.to_owned(),
-                    ));
+            }
+        } else {
+            serde_json::to_writer_pretty(&mut f, self)?;
+        }
+        Ok(())
+    }
+}
+
+/// Split the files into the unique ones, the groups of byte-identical copies, as the kept one and the others,
+/// and the ones failed to read.
+#[allow(clippy::type_complexity)]
+fn identical(
+    paths: &[PathBuf],
+    keep: &KeepArgs,
+    jobs: usize,
+) -> Result<
+    (
+        Vec<PathBuf>,
+        Vec<(PathBuf, Vec<PathBuf>)>,
+        Vec<(PathBuf, Error)>,
+    ),
+    Error,
+> {
+    // same size first, then same checksum
+    let mut unreadable: Vec<Option<Error>> = paths.iter().map(|_| None).collect();
+    let mut sizes: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (idx, path) in paths.iter().enumerate() {
+        match stat(path) {
+            Ok(x) => sizes.entry(x.0).or_default().push(idx),
+            Err(e) => unreadable[idx] = Some(e),
+        }
+    }
+    let mut candidates = sizes
+        .into_iter()
+        .filter(|(_, v)| v.len() > 1)
+        .flat_map(|(size, v)| v.into_iter().map(move |idx| (size, idx)))
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|x| x.1);
+    let checksums = par_map(
+        &candidates
+            .iter()
+            .map(|&(_, idx)| paths[idx].to_path_buf())
+            .collect::<Vec<_>>(),
+        jobs,
+        "Checksumming",
+        |p| checksum(p),
+    )?;
+    let mut groups: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+    for (&(size, idx), checksum) in candidates.iter().zip(checksums) {
+        match checksum {
+            Ok(x) => groups.entry((size, x)).or_default().push(idx),
+            Err(e) => unreadable[idx] = Some(e),
+        }
+    }
+    // ordered by the paths, so that the kept copies and group ids don't depend on the input order
+    let mut groups = groups
+        .into_values()
+        .filter(|v| v.len() > 1)
+        .collect::<Vec<_>>();
+    groups
+        .iter_mut()
+        .for_each(|v| v.sort_by(|&a, &b| paths[a].cmp(&paths[b])));
+    groups.sort_by(|a, b| paths[a[0]].cmp(&paths[b[0]]));
+    let mut is_copy = vec![false; paths.len()];
+    let mut copies: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+    for v in groups.iter() {
+        let best = keep.best(&v.iter().map(|&k| (&paths[k], (0, 0))).collect::<Vec<_>>())?;
+        let mut others: Vec<PathBuf> = Vec::new();
+        for (i, &k) in v.iter().enumerate() {
+            if i != best {
+                is_copy[k] = true;
+                others.push(paths[k].to_path_buf());
+            }
+        }
+        copies.push((paths[v[best]].to_path_buf(), others));
+    }
+    let (mut uniques, mut v_unreadable) = (Vec::new(), Vec::new());
+    for ((path, e), is_copy) in paths.iter().zip(unreadable).zip(is_copy) {
+        match (e, is_copy) {
+            (Some(e), _) => v_unreadable.push((path.to_path_buf(), e)),
+            (None, false) => uniques.push(path.to_path_buf()),
+            _ => {}
+        }
+    }
+    Ok((uniques, copies, v_unreadable))
+}
+
+/// Disjoint sets over `0..n`, with the smallest index as the root.
+struct UnionFind(Vec<usize>);
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        Self((0..n).collect())
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.0[x] != x {
+            self.0[x] = self.0[self.0[x]];
+            x = self.0[x];
+        }
+        x
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.0[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+/// Feats and dimensions of the images, `None` for the ones failed to decode, pushed to `deprecated` with the errors.
+#[allow(clippy::type_complexity)]
+fn feats_of(
+    paths: &[PathBuf],
+    extractor: &Extractor,
+    jobs: usize,
+    deprecated: &mut Vec<(PathBuf, Error)>,
+) -> Result<Vec<Option<(Feat, (u32, u32))>>, Error> {
+    let ys = par_map(paths, jobs, "Deduplicating", |p| match decode(p) {
+        Err(e) => Ok(Err(Error::decoding(p, e))),
+        Ok(img) => extractor.extract(&img).map(|x| Ok((x, img.dimensions()))),
+    })?;
+    let mut feats = Vec::with_capacity(paths.len());
+    for (p, y) in paths.iter().zip(ys) {
+        match y? {
+            Ok(x) => feats.push(Some(x)),
+            Err(e) => {
+                deprecated.push((p.to_path_buf(), e));
+                feats.push(None);
+            }
+        }
+    }
+    Ok(feats)
+}
+
+/// Options of `deduplicate`.
+#[derive(Debug, Clone, Default)]
+pub struct DedupOptions {
+    /// Distance within which the images are similar, the default of the extractor if `None`
+    pub thresh: Option<f32>,
+    /// Policy choosing the survivor of each group
+    pub keep: KeepArgs,
+    /// Group byte-identical copies by size and checksum before decoding
+    pub exact: bool,
+    /// List the pairwise distances of each group too, see `Group::distances`
+    pub pairs: bool,
+    /// Number of threads, 0 for all the cores
+    pub jobs: usize,
+}
+
+/// Group the similar images, keeping one of each group.
+pub fn deduplicate(
+    paths: &[PathBuf],
+    extractor: &Extractor,
+    options: &DedupOptions,
+) -> Result<Deduplication, Error> {
+    let DedupOptions {
+        thresh,
+        keep,
+        exact,
+        pairs,
+        jobs,
+    } = options;
+    let (pairs, jobs) = (*pairs, *jobs);
+    let keep = keep.prepared()?;
+
+    // byte-identical
+    let (paths, identical_groups, unreadable) = if *exact {
+        identical(paths, &keep, jobs)?
+    } else {
+        (paths.to_vec(), Vec::new(), Vec::new())
+    };
+    let thresh = thresh.unwrap_or(extractor.thresh());
+    let mut v_deps: Vec<(PathBuf, Error)> = unreadable;
+    let feats = feats_of(&paths, extractor, jobs, &mut v_deps)?;
+
+    // identical hashes share one key of the index (the bucket), so each is searched once
+    let mut buckets: Vec<Vec<usize>> = Vec::new();
+    let mut seen: HashMap<&[u8], usize> = HashMap::new();
+    for (idx, feat) in feats.iter().enumerate() {
+        match feat {
+            None => {}
+            Some((Feat::Bits(x), _)) => match seen.get(x.as_slice()) {
+                Some(&b) => buckets[b].push(idx),
+                None => {
+                    seen.insert(x, buckets.len());
+                    buckets.push(vec![idx]);
                 }
-            });
+            },
+            Some(_) => buckets.push(vec![idx]),
+        }
+    }
+    let index = usearch::Index::new(&extractor.index_options()).map_err(Error::index)?;
+    index.reserve(buckets.len()).map_err(Error::index)?;
+    for (b, v) in buckets.iter().enumerate() {
+        feats[v[0]].as_ref().unwrap().0.register(&index, b as u64)?;
+    }
+
+    // similarity graph, clustered into connected components
+    let pb = build_pb(buckets.len() as u64, "Clustering");
+    let mut uf = UnionFind::new(paths.len());
+    for v in buckets.iter() {
+        pb.inc(1);
+        for &k in v[1..].iter() {
+            uf.union(v[0], k);
+        }
+        for (b, _) in feats[v[0]].as_ref().unwrap().0.search(&index, thresh)? {
+            uf.union(v[0], buckets[b as usize][0]);
+        }
+    }
+    pb.finish();
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for idx in (0..paths.len()).filter(|&k| feats[k].is_some()) {
+        groups.entry(uf.find(idx)).or_default().push(idx);
+    }
+
+    // ordered by the paths, so that group ids don't depend on the walk order
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    groups
+        .iter_mut()
+        .for_each(|v| v.sort_by(|&a, &b| paths[a].cmp(&paths[b])));
+    groups.sort_by(|a, b| paths[a[0]].cmp(&paths[b[0]]));
 
-            // deal with duplicates
-            if _v_dup.is_empty() {
-                maps_curated.insert(path.to_path_buf(), feat);
+    // choose the best of each group and remove the others
+    let mut y = Deduplication {
+        thresh,
+        groups: Vec::new(),
+        files: Vec::new(),
+        curated: Vec::new(),
+        duplicated: Vec::new(),
+        identical: Vec::new(),
+        deprecated: Vec::new(),
+    };
+    let canon = paths.iter().map(canonical).collect::<Result<Vec<_>, _>>()?;
+    let feat = |k: usize| &feats[k].as_ref().unwrap().0;
+    let member = |k: usize, kept: usize| -> Result<Member, Error> {
+        let (w, h) = feats[k].as_ref().unwrap().1;
+        Ok(Member {
+            path: canon[k].to_path_buf(),
+            width: Some(w),
+            height: Some(h),
+            size: stat(&paths[k])?.0,
+            distance: feat(k).distance(feat(kept)),
+            root: None,
+        })
+    };
+    let status = |path: &PathBuf, status, group| Status {
+        path: path.to_path_buf(),
+        status,
+        group,
+        root: None,
+    };
+    for v in groups.iter() {
+        if v.len() == 1 {
+            y.curated.push(paths[v[0]].to_path_buf());
+            y.files.push(status(&canon[v[0]], "curated", None));
+            continue;
+        }
+        let id = y.groups.len();
+        let best = keep.best(
+            &v.iter()
+                .map(|&k| (&paths[k], feats[k].as_ref().map_or((0, 0), |x| x.1)))
+                .collect::<Vec<_>>(),
+        )?;
+        let kept = v[best];
+        let distances = match pairs {
+            true => pairs_of(
+                &v.iter().map(|&k| &canon[k]).collect::<Vec<_>>(),
+                best,
+                |a, b| feat(v[a]).distance(feat(v[b])),
+            ),
+            false => Vec::new(),
+        };
+        let mut removed: Vec<Member> = Vec::new();
+        for &k in v.iter() {
+            if k == kept {
+                y.curated.push(paths[k].to_path_buf());
+                y.files.push(status(&canon[k], "curated", Some(id)));
             } else {
-                _v_dup.push((
-                    std::fs::metadata(path).unwrap().len(),
-                    path.to_path_buf(),
-                    feat.to_owned(),
-                ));
-
-                // choose the best and remove the others
-                _v_dup.par_sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-                for (idx, (_size, path, feat)) in _v_dup.iter().enumerate() {
-                    if idx == 0 {
-                        maps_curated.insert(path.to_path_buf(), *feat);
-                        continue;
-                    }
-                    maps_curated.remove(path);
-                    v_dups.push(path.to_path_buf());
+                y.duplicated.push(paths[k].to_path_buf());
+                y.files.push(status(&canon[k], "duplicated", Some(id)));
+                removed.push(member(k, kept)?);
+            }
+        }
+        y.groups.push(Group {
+            id,
+            exact: false,
+            kept: member(kept, kept)?,
+            removed,
+            distances,
+        });
+    }
+
+    // byte-identical groups come after, not decoded
+    let copy = |p: &PathBuf| -> Result<Member, Error> {
+        Ok(Member {
+            path: canonical(p)?,
+            width: None,
+            height: None,
+            size: stat(p)?.0,
+            distance: 0.,
+            root: None,
+        })
+    };
+    for (kept, others) in identical_groups.iter() {
+        let id = y.groups.len();
+        let kept = copy(kept)?;
+        let removed = others.iter().map(copy).collect::<Result<Vec<_>, _>>()?;
+        let distances = match pairs {
+            true => pairs_of(
+                &std::iter::once(&kept)
+                    .chain(removed.iter())
+                    .map(|x| &x.path)
+                    .collect::<Vec<_>>(),
+                0,
+                |_, _| 0.,
+            ),
+            false => Vec::new(),
+        };
+        // the kept one is listed with the group of its copies, unless in a perceptual one
+        if let Some(x) = y
+            .files
+            .iter_mut()
+            .find(|x| x.path == kept.path && x.group.is_none())
+        {
+            x.group = Some(id);
+        }
+        for (p, x) in others.iter().zip(removed.iter()) {
+            y.identical.push(p.to_path_buf());
+            y.files.push(status(&x.path, "identical", Some(id)));
+        }
+        y.groups.push(Group {
+            id,
+            exact: true,
+            kept,
+            removed,
+            distances,
+        });
+    }
+    for (p, _) in v_deps.iter() {
+        // one gone since the walk has no canonical path
+        let path = canonical(p).unwrap_or_else(|_| p.to_path_buf());
+        y.files.push(status(&path, "deprecated", None));
+    }
+    y.files.sort_by(|a, b| a.path.cmp(&b.path));
+    v_deps.sort_by(|a, b| a.0.cmp(&b.0));
+    y.deprecated = v_deps;
+
+    Ok(y)
+}
+
+/// Pairs of the members of a group with their distances, `xs[kept]` the kept one.
+/// Groups of more than `MAX_PAIRWISE` members have the pairs with the kept one only.
+fn pairs_of<F: Fn(usize, usize) -> f32>(xs: &[&PathBuf], kept: usize, distance: F) -> Vec<Pair> {
+    let n = xs.len();
+    let ys: Vec<(usize, usize)> = match n <= MAX_PAIRWISE {
+        true => (0..n)
+            .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+            .collect(),
+        false => (0..n).filter(|&b| b != kept).map(|b| (kept, b)).collect(),
+    };
+    ys.into_iter()
+        .map(|(a, b)| Pair {
+            a: xs[a].to_path_buf(),
+            b: xs[b].to_path_buf(),
+            distance: distance(a, b),
+        })
+        .collect()
+}
+
+/// Flag the images matching any item of the `reference` index within `thresh` (the default of the extractor if `None`),
+/// grouped by the nearest item, as the kept one. `refs` are the entries of the keys, the reference is never modified.
+pub fn deduplicate_against(
+    paths: &[PathBuf],
+    extractor: &Extractor,
+    reference: &usearch::Index,
+    refs: &HashMap<u64, Entry>,
+    thresh: Option<f32>,
+    jobs: usize,
+) -> Result<Deduplication, Error> {
+    let thresh = thresh.unwrap_or(extractor.thresh());
+    let mut v_deps: Vec<(PathBuf, Error)> = Vec::new();
+    let feats = feats_of(paths, extractor, jobs, &mut v_deps)?;
+
+    // nearest reference item of each
+    let pb = build_pb(paths.len() as u64, "Matching");
+    let mut groups: BTreeMap<&PathBuf, _> = BTreeMap::new();
+    let mut y = Deduplication {
+        thresh,
+        groups: Vec::new(),
+        files: Vec::new(),
+        curated: Vec::new(),
+        duplicated: Vec::new(),
+        identical: Vec::new(),
+        deprecated: v_deps,
+    };
+    for (idx, (path, feat)) in paths.iter().zip(feats.iter()).enumerate() {
+        pb.inc(1);
+        match feat {
+            None => {}
+            Some((feat, _)) => {
+                let nearest = feat
+                    .search(reference, thresh)?
+                    .into_iter()
+                    .find_map(|(k, d)| refs.get(&k).map(|x| (x, d)));
+                match nearest {
+                    None => y.curated.push(path.to_path_buf()),
+                    Some((x, d)) => groups
+                        .entry(&x.path)
+                        .or_insert((x, vec![]))
+                        .1
+                        .push((idx, d)),
+                }
+            }
+        }
+    }
+    pb.finish();
+
+    // groups ordered by the reference paths
+    let status = |p: &PathBuf, status, group| -> Result<Status, Error> {
+        Ok(Status {
+            path: canonical(p)?,
+            status,
+            group,
+            root: None,
+        })
+    };
+    for (id, (kept, mut v)) in groups.into_values().enumerate() {
+        v.sort_by(|a, b| paths[a.0].cmp(&paths[b.0]));
+        let mut removed: Vec<Member> = Vec::new();
+        let mut distances: Vec<Pair> = Vec::new();
+        for (k, d) in v.into_iter() {
+            let (w, h) = feats[k].as_ref().unwrap().1;
+            let path = canonical(&paths[k])?;
+            y.duplicated.push(paths[k].to_path_buf());
+            y.files.push(status(&paths[k], "duplicated", Some(id))?);
+            distances.push(Pair {
+                a: kept.path.to_path_buf(),
+                b: path.to_path_buf(),
+                distance: d,
+            });
+            removed.push(Member {
+                path,
+                width: Some(w),
+                height: Some(h),
+                size: stat(&paths[k])?.0,
+                distance: d,
+                root: None,
+            });
+        }
+        y.groups.push(Group {
+            id,
+            exact: false,
+            kept: Member {
+                path: kept.path.to_path_buf(),
+                width: None,
+                height: None,
+                size: kept.size,
+                distance: 0.,
+                root: None,
+            },
+            removed,
+            distances,
+        });
+    }
+    for p in y.curated.iter() {
+        y.files.push(status(p, "curated", None)?);
+    }
+    for (p, _) in y.deprecated.iter() {
+        y.files.push(status(p, "deprecated", None)?);
+    }
+    y.files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(y)
+}
+
+impl Args {
+    /// Extractor, index and entries of `--reference`, the method of a saved index is used instead of `--method`.
+    /// Rejected before the index is built if any of `paths` is in the reference, which is never touched.
+    /// The entries of a folder have no checksum, their files are decoded only.
+    #[allow(clippy::type_complexity)]
+    fn load_reference(
+        &self,
+        reference: &Path,
+        paths: &[PathBuf],
+    ) -> Result<(Method, Extractor, usearch::Index, HashMap<u64, Entry>)> {
+        let v_inputs: HashSet<PathBuf> = paths.iter().map(canonical).collect::<Result<_, _>>()?;
+        let overlaps = |x: &PathBuf| match v_inputs.contains(x) {
+            true => Err(Error::Options(format!(
+                "The input overlaps the reference: {}",
+                x.display()
+            ))),
+            false => Ok(()),
+        };
+        let saved = reference.to_str().map(Manifest::path);
+        if reference.is_file() && saved.is_some_and(|x| x.exists()) {
+            let (manifest, extractor, index) =
+                Manifest::load_with_index(reference.to_str().unwrap())?;
+            manifest
+                .entries
+                .iter()
+                .try_for_each(|x| overlaps(&x.path))?;
+            LOGGER.success("Reference", &format!("{}", reference.display()), "Index");
+            LOGGER.success("", "Size", &format!("{}", index.size()));
+            let refs = manifest.entries.into_iter().map(|x| (x.key, x)).collect();
+            return Ok((manifest.method, extractor, index, refs));
+        }
+        let filter = FilterArgs {
+            list: None,
+            ..self.filter.clone()
+        };
+        let walked = walk(reference, self.recursive, false, &filter)?;
+        log_source("Reference", self.recursive, &filter, &walked)?;
+        let v_refs = walked
+            .files
+            .iter()
+            .map(canonical)
+            .collect::<Result<Vec<_>, _>>()?;
+        v_refs.iter().try_for_each(overlaps)?;
+        let extractor = Extractor::new(self.method, self.bits, Some(&self.nn))?;
+        let (index, _) = build_index(&walked.files, &extractor, self.jobs)?;
+        LOGGER.success("", "Size", &format!("{}", index.size()));
+        let refs = walked
+            .files
+            .iter()
+            .zip(v_refs)
+            .enumerate()
+            .filter(|(k, _)| index.contains(*k as u64))
+            .map(|(k, (p, path))| {
+                let (size, mtime) = stat(p)?;
+                let key = k as u64;
+                Ok((
+                    key,
+                    Entry {
+                        key,
+                        root: walked.root_of(&path),
+                        path,
+                        size,
+                        mtime,
+                        checksum: None,
+                    },
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok((self.method, extractor, index, refs))
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let mut walked = walk_all(&self.input, self.recursive, false, &self.filter)?;
+        log_source("Source", self.recursive, &self.filter, &walked)?;
+        let paths = std::mem::take(&mut walked.files);
+        let (method, mut y) = match &self.reference {
+            None => {
+                let extractor = Extractor::new(self.method, self.bits, Some(&self.nn))?;
+                let options = DedupOptions {
+                    thresh: self.thresh,
+                    keep: self.keep.clone(),
+                    exact: self.exact,
+                    pairs: is_json() || self.report.as_deref().is_some_and(|x| !Report::is_csv(x)),
+                    jobs: self.jobs,
+                };
+                let y = deduplicate(&paths, &extractor, &options)?;
+                (self.method, y)
+            }
+            Some(reference) => {
+                let (method, extractor, index, refs) = self.load_reference(reference, &paths)?;
+                let y =
+                    deduplicate_against(&paths, &extractor, &index, &refs, self.thresh, self.jobs)?;
+                (method, y)
+            }
+        };
+        let cnt_groups = y.groups.iter().filter(|x| !x.exact).count();
+
+        // the input of each file, for more than one
+        if !walked.roots.is_empty() {
+            for g in y.groups.iter_mut() {
+                for x in std::iter::once(&mut g.kept).chain(g.removed.iter_mut()) {
+                    x.root = walked.root_of(&x.path);
                 }
             }
+            for x in y.files.iter_mut() {
+                x.root = walked.root_of(&x.path);
+            }
         }
-        pb.finish();
+
+        // show groups
+        if self.verbose {
+            for g in y.groups.iter() {
+                let kind = match g.exact {
+                    true => "Identical",
+                    false => "",
+                };
+                LOGGER.success("Group", &format!("{}", g.id), kind);
+                let kept = match self.reference {
+                    None => "Kept",
+                    Some(_) => "Reference",
+                };
+                LOGGER.success("", &format!("{}", g.kept.path.display()), kept);
+                for x in g.removed.iter() {
+                    LOGGER.success("", &format!("{}", x.path.display()), "Removed");
+                }
+            }
+        }
+        let report = Report {
+            method,
+            thresh: y.thresh,
+            groups: &y.groups,
+        };
+        if let Some(path) = &self.report {
+            report.save(path)?;
+            LOGGER.success("Report saved to", &format!("{}", path.display()), "");
+        }
+        let deprecated = y
+            .deprecated
+            .iter()
+            .map(|(p, e)| Failed::new(p, Some(e)))
+            .collect();
+        let mut doc = Document {
+            command: "dedup",
+            collection: Collection::new(&walked, self.recursive, deprecated),
+            counts: BTreeMap::from([
+                ("groups", cnt_groups),
+                ("identical", y.identical.len()),
+                ("duplicated", y.duplicated.len()),
+                ("curated", y.curated.len()),
+                ("deprecated", y.deprecated.len()),
+            ]),
+            files: &y.files,
+            reference: self
+                .reference
+                .as_ref()
+                .map(|x| x.canonicalize())
+                .transpose()?,
+            report: &report,
+            saved: Saved::default(),
+        };
 
         // summary
         LOGGER.success("Found", "", "");
-        LOGGER.success("", SAVEOUT_DUPLICATED, &format!("x{}", v_dups.len()));
-        LOGGER.success("", SAVEOUT_CURATED, &format!("x{}", maps_curated.len()));
-        LOGGER.success("", SAVEOUT_DEPRECATED, &format!("x{}", v_deps.len()));
+        if self.exact {
+            LOGGER.success("", SAVEOUT_IDENTICAL, &format!("x{}", y.identical.len()));
+        }
+        LOGGER.success("", "Groups", &format!("x{}", cnt_groups));
+        LOGGER.success("", SAVEOUT_DUPLICATED, &format!("x{}", y.duplicated.len()));
+        LOGGER.success("", SAVEOUT_CURATED, &format!("x{}", y.curated.len()));
+        LOGGER.success("", SAVEOUT_DEPRECATED, &format!("x{}", y.deprecated.len()));
 
         // show deprecated
         if self.show_deprecated {
-            if v_deps.is_empty() {
+            if y.deprecated.is_empty() {
                 LOGGER.success("Unsupported Files Or Deprecated Images", "Not Found", "");
             } else {
                 LOGGER.warn("Unsupported Files Or Deprecated Images", "", "");
-                for p in v_deps.iter() {
-                    LOGGER.warn("", &format!("{}", p.canonicalize()?.display()), "");
+                for (p, e) in y.deprecated.iter() {
+                    LOGGER.warn("", &format!("{}", canonical(p)?.display()), e.category());
                 }
             }
         }
 
-        if v_dups.is_empty() {
+        if y.duplicated.is_empty() && y.identical.is_empty() {
+            doc.saved.plan = self.save.plan_nothing()?;
+            if is_json() {
+                return print_json(&doc);
+            }
             println!(
                 "\n🎉 All the images seem non-duplicate under the current threshold: {}",
-                self.thresh
+                y.thresh
             );
-            if !v_deps.is_empty() {
+            if !y.deprecated.is_empty() {
                 println!(
                     "\n❗️ Note that there are {:?} files that are unsupported or deprecated",
-                    v_deps.len()
+                    y.deprecated.len()
                 );
             }
             return Ok(());
         }
 
-        match &self.output {
-            None => LOGGER.exit(
-                "Results",
-                "Not Saving",
-                "Use `-o <PATH>` to set the save location",
-            ),
+        match &self.save.output {
+            None if self.save.report_only() => {}
+            None => return Err(Error::NoOutput.into()),
             Some(output) => {
+                let mut plan = self.save.plan();
+                let mut results = vec![(SAVEOUT_CURATED, &y.curated)];
+                if !self.curated_only {
+                    results.push((SAVEOUT_DUPLICATED, &y.duplicated));
+                    results.push((SAVEOUT_IDENTICAL, &y.identical));
+                }
                 let pb = build_pb(
-                    (maps_curated.len() + v_dups.len()) as u64,
-                    if !self.mv {
-                        "Saving[Copy]"
-                    } else {
-                        "Saving[Move]"
-                    },
+                    results.iter().map(|x| x.1.len()).sum::<usize>() as u64,
+                    &format!("Saving[{}]", plan.transfer().name()),
                 );
-                // make dirs
-                let saveout = make_folders(output)?;
-                let mut saveout_dups = saveout.clone();
-                let mut saveout_curated = saveout.clone();
-                saveout_dups.push(SAVEOUT_DUPLICATED);
-                saveout_curated.push(SAVEOUT_CURATED);
-                std::fs::create_dir_all(&saveout_dups)?;
-                std::fs::create_dir_all(&saveout_curated)?;
-                for path in maps_curated.into_keys() {
-                    pb.inc(1);
-                    let name = path.file_name().unwrap().to_str().unwrap();
-                    saveout_curated.push(name);
-                    src2dst(&path, &saveout_curated, self.mv)?;
-                    saveout_curated.pop();
-                }
-                for path in v_dups.into_iter() {
-                    pb.inc(1);
-                    let name = path.file_name().unwrap().to_str().unwrap();
-                    saveout_dups.push(name);
-                    src2dst(&path, &saveout_dups, self.mv)?;
-                    saveout_dups.pop();
+                let saveout = plan.folder(output)?;
+                let root = match self.save.preserve_tree {
+                    true => Some(walked.root.to_path_buf()),
+                    false => None,
+                };
+                for (reason, paths) in results {
+                    let dir = saveout.join(reason);
+                    for path in paths.iter() {
+                        pb.inc(1);
+                        let name = path.file_name().unwrap();
+                        plan.save(path, name, root.as_deref(), &dir, reason)?;
+                    }
                 }
                 pb.finish();
-                LOGGER.success(
-                    "Results saved to",
-                    &format!("{}", saveout.canonicalize()?.display()),
-                    "",
-                );
+                doc.saved.output = Some(saveout.to_path_buf());
+                plan.finish()?;
+                doc.saved.plan = plan.to_stdout().then_some(plan);
+            }
+        }
+        if is_json() {
+            print_json(&doc)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components(n: usize, edges: &[(usize, usize)]) -> Vec<usize> {
+        let mut uf = UnionFind::new(n);
+        for &(a, b) in edges.iter() {
+            uf.union(a, b);
+        }
+        (0..n).map(|x| uf.find(x)).collect()
+    }
+
+    #[test]
+    fn union_find_order_independent() {
+        let edges = [(5, 3), (3, 1), (7, 8), (0, 9), (9, 4), (8, 6)];
+        let ys = components(10, &edges);
+        assert_eq!(ys, [0, 1, 2, 1, 0, 1, 6, 6, 6, 0]);
+
+        // reversed, and each edge flipped
+        let mut edges = edges.iter().map(|&(a, b)| (b, a)).collect::<Vec<_>>();
+        edges.reverse();
+        assert_eq!(components(10, &edges), ys);
+        edges.rotate_left(2);
+        assert_eq!(components(10, &edges), ys);
+    }
+
+    #[test]
+    fn groups_independent_of_input_order() -> Result<()> {
+        use image::{imageops, Rgb, RgbImage};
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let mut paths: Vec<PathBuf> = Vec::new();
+        // each pattern, a larger copy and a byte-identical copy
+        for seed in [1u32, 6, 17] {
+            let img = RgbImage::from_fn(32, 32, |x, y| {
+                let v = ((x * seed + y * (seed + 3)) % 256) as u8;
+                Rgb([v, v / 2, 255 - v])
+            });
+            let (a, b, c) = (
+                dir.join(format!("{}.bmp", seed)),
+                dir.join(format!("{}-large.bmp", seed)),
+                dir.join(format!("{}-copy.bmp", seed)),
+            );
+            img.save(&a)?;
+            imageops::resize(&img, 48, 48, imageops::FilterType::Triangle).save(&b)?;
+            std::fs::copy(&a, &c)?;
+            paths.extend([a, b, c]);
+        }
+        std::fs::write(dir.join("bad.bmp"), "not an image")?;
+        paths.push(dir.join("bad.bmp"));
+
+        let extractor = Extractor::new(Method::BlockHash, None, None)?;
+        let options = DedupOptions {
+            exact: true,
+            ..Default::default()
+        };
+        let dedup = |paths: &[PathBuf]| -> Result<_> {
+            let y = deduplicate(paths, &extractor, &options)?;
+            let groups = y
+                .groups
+                .iter()
+                .map(|g| {
+                    let removed = g.removed.iter().map(|x| x.path.clone()).collect::<Vec<_>>();
+                    (g.id, g.exact, g.kept.path.clone(), removed)
+                })
+                .collect::<Vec<_>>();
+            let files = y
+                .files
+                .iter()
+                .map(|x| (x.path.clone(), x.status, x.group))
+                .collect::<Vec<_>>();
+            Ok((groups, files))
+        };
+        let (groups, files) = dedup(&paths)?;
+        assert_eq!(groups.iter().filter(|x| x.1).count(), 3);
+        assert_eq!(groups.iter().filter(|x| !x.1).count(), 3);
+        // the largest of each pattern kept
+        for (_, _, kept, _) in groups.iter().filter(|x| !x.1) {
+            assert!(kept.to_string_lossy().ends_with("-large.bmp"), "{:?}", kept);
+        }
+
+        // reversed, rotated
+        let mut xs = paths.clone();
+        for k in 0..xs.len() {
+            match k % 2 {
+                0 => xs.reverse(),
+                _ => xs.rotate_left(k),
             }
+            assert_eq!(dedup(&xs)?, (groups.clone(), files.clone()), "{:?}", xs);
         }
+        Ok(())
+    }
 
+    #[test]
+    fn reference_overlapping_input() -> Result<()> {
+        use clap::Parser;
+        let tmp = tempfile::tempdir()?;
+        let input = tmp.path().join("input");
+        std::fs::create_dir_all(input.join("sub"))?;
+        for name in ["a.bmp", "sub/b.bmp"] {
+            image::RgbImage::new(8, 8).save(input.join(name))?;
+        }
+        let dedup = |reference: &Path| {
+            let (i, r) = (input.to_str().unwrap(), reference.to_str().unwrap());
+            let argv = ["dedup", "-i", i, "-r", "--reference", r, "--dry-run", "-"];
+            Args::parse_from(argv).run()
+        };
+
+        // a folder of the input, or the input itself
+        for reference in [input.join("sub"), input.clone()] {
+            let e = dedup(&reference).unwrap_err();
+            assert!(e.to_string().contains("overlaps"), "{}", e);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,55 @@
+//! Parts shared by the documents of `--format json`.
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::{canonical, Error, Plan, Walked};
+
+/// File failed to read or decode.
+#[derive(Serialize, Debug)]
+pub(crate) struct Failed {
+    path: PathBuf,
+    /// Category of the error, unknown for the ones of a saved index
+    category: Option<&'static str>,
+    error: Option<String>,
+}
+
+impl Failed {
+    pub(crate) fn new(path: &Path, e: Option<&Error>) -> Self {
+        Self {
+            path: canonical(path).unwrap_or_else(|_| path.to_path_buf()),
+            category: e.map(Error::category),
+            error: e.map(|e| e.to_string()),
+        }
+    }
+}
+
+/// Files a command ran on.
+#[derive(Serialize, Debug)]
+pub(crate) struct Collection<'a> {
+    pub input: PathBuf,
+    pub recursive: bool,
+    /// Inputs, symlinks and the files reachable more than once
+    #[serde(skip_serializing_if = "Walked::is_plain")]
+    pub walk: &'a Walked,
+    pub deprecated: Vec<Failed>,
+}
+
+impl<'a> Collection<'a> {
+    pub(crate) fn new(walk: &'a Walked, recursive: bool, deprecated: Vec<Failed>) -> Self {
+        Self {
+            input: walk.source.to_path_buf(),
+            recursive,
+            walk,
+            deprecated,
+        }
+    }
+}
+
+/// Where the results were saved.
+#[derive(Serialize, Debug, Default)]
+pub(crate) struct Saved {
+    pub output: Option<PathBuf>,
+    /// Plan of `--dry-run -`, here instead of a second document on stdout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<Plan>,
+}
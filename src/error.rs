@@ -0,0 +1,256 @@
+use std::path::{Path, PathBuf};
+
+use crate::{guess_format, Transfer};
+
+/// Errors of the library API, the CLI prints them then exits.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Not exist: {}", .0.display())]
+    NotExist(PathBuf),
+
+    #[error("Is symlink: {}", .0.display())]
+    Symlink(PathBuf),
+
+    #[error("Incorrect path: {}", .0.display())]
+    InvalidPath(PathBuf),
+
+    #[error("Error when saving [{}] {} to {}: {source}", .transfer.name(), .src.display(), .dst.display())]
+    Transfer {
+        src: PathBuf,
+        dst: PathBuf,
+        transfer: Transfer,
+        source: std::io::Error,
+    },
+
+    /// The file can't be opened or read
+    #[error("Unreadable: {}: {source}", .path.display())]
+    Unreadable {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    /// The format can't be guessed from the content
+    #[error("Unknown format: {}", .0.display())]
+    UnknownFormat(PathBuf),
+
+    /// The data ends before the image does
+    #[error("Truncated: {}: {reason}", .path.display())]
+    Truncated { path: PathBuf, reason: String },
+
+    /// The data of a known format is invalid
+    #[error("Corrupted: {}: {reason}", .path.display())]
+    Corrupted { path: PathBuf, reason: String },
+
+    /// The format is known but its decoder isn't supported
+    #[error("Unsupported codec: {}: {reason}", .path.display())]
+    UnsupportedCodec { path: PathBuf, reason: String },
+
+    /// The image exceeds the dimension or memory limits of the decoder
+    #[error("Dimension limit exceeded: {}: {reason}", .path.display())]
+    DimensionLimit { path: PathBuf, reason: String },
+
+    #[error("Invalid list: {}, line {line}: {reason}", .path.display())]
+    List {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+
+    #[error("Invalid archive: {}: {reason}", .path.display())]
+    Archive { path: PathBuf, reason: String },
+
+    #[error("Invalid glob pattern: {0}")]
+    Glob(globset::Error),
+
+    /// Failures of the usearch index
+    #[error("Index: {0}")]
+    Index(String),
+
+    /// Missing or invalid options, e.g. `--bits` or `--priority`
+    #[error("Invalid options: {0}")]
+    Options(String),
+
+    /// The onnx model or the tokenizer can't be loaded or run
+    #[error("Invalid model: {}: {reason}", .path.display())]
+    Model { path: PathBuf, reason: String },
+
+    /// Nothing retrived within `--thresh`
+    #[error("No image retrived, try a larger `--thresh`")]
+    NoMatch,
+
+    /// The results are found but `-o` is not given
+    #[error("Results not saved, use `-o <PATH>` to set the save location")]
+    NoOutput,
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// Classify the failure of decoding the image at `path`.
+    pub fn decoding(path: &Path, e: image::ImageError) -> Self {
+        use image::error::{ImageFormatHint, UnsupportedErrorKind};
+        use image::ImageError;
+
+        let path = path.to_path_buf();
+        let reason = e.to_string();
+        match e {
+            // ended before the signature, or within the content of a known format
+            ImageError::IoError(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                match guess_format(&path) {
+                    None => Self::UnknownFormat(path),
+                    Some(_) => Self::Truncated { path, reason },
+                }
+            }
+            ImageError::IoError(source) => Self::Unreadable { path, source },
+            ImageError::Unsupported(x) => match x.kind() {
+                UnsupportedErrorKind::Format(ImageFormatHint::Unknown) => Self::UnknownFormat(path),
+                _ => Self::UnsupportedCodec { path, reason },
+            },
+            ImageError::Limits(_) => Self::DimensionLimit { path, reason },
+            ImageError::Encoding(_) => Self::UnsupportedCodec { path, reason },
+
+            // decoded by the format of the extension, the content of none
+            ImageError::Decoding(_) if guess_format(&path).is_none() => Self::UnknownFormat(path),
+            ImageError::Decoding(_) | ImageError::Parameter(_) => Self::Corrupted { path, reason },
+        }
+    }
+
+    pub fn index<E: std::fmt::Display>(e: E) -> Self {
+        Self::Index(e.to_string())
+    }
+
+    pub fn model<E: std::fmt::Display>(path: &Path, e: E) -> Self {
+        Self::Model {
+            path: path.to_path_buf(),
+            reason: format!("{:#}", e),
+        }
+    }
+
+    /// Name of the category, also the folder of the files failed to check.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::Unreadable { .. } => "Unreadable",
+            Self::UnknownFormat(_) => "Unknown Format",
+            Self::Truncated { .. } => "Truncated",
+            Self::Corrupted { .. } => "Corrupted",
+            Self::UnsupportedCodec { .. } => "Unsupported Codec",
+            Self::DimensionLimit { .. } => "Dimension Limit",
+            Self::Index(_) => "Index",
+            _ => "Other",
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<Error>() {
+            Ok(e) => e,
+            Err(e) => Error::Other(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::error::{
+        DecodingError, ImageFormatHint, LimitError, LimitErrorKind, ParameterError,
+        ParameterErrorKind, UnsupportedError, UnsupportedErrorKind,
+    };
+    use image::{ImageError, ImageFormat};
+
+    #[test]
+    fn decoding_categories() -> std::io::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let png = tmp.path().join("a.png");
+        std::fs::write(&png, b"\x89PNG\r\n\x1a\n not a png")?;
+        let unknown = tmp.path().join("b.png");
+        std::fs::write(&unknown, b"not an image")?;
+
+        let hint = ImageFormatHint::Exact(ImageFormat::Png);
+        let unsupported = |kind| UnsupportedError::from_format_and_kind(hint.clone(), kind);
+        let xs: Vec<(ImageError, &Path, &str)> = vec![
+            (
+                ImageError::IoError(std::io::ErrorKind::UnexpectedEof.into()),
+                &png,
+                "Truncated",
+            ),
+            (
+                ImageError::IoError(std::io::ErrorKind::UnexpectedEof.into()),
+                &unknown,
+                "Unknown Format",
+            ),
+            (
+                ImageError::IoError(std::io::ErrorKind::PermissionDenied.into()),
+                &png,
+                "Unreadable",
+            ),
+            (
+                ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+                    ImageFormatHint::Unknown,
+                    UnsupportedErrorKind::Format(ImageFormatHint::Unknown),
+                )),
+                &unknown,
+                "Unknown Format",
+            ),
+            (
+                ImageError::Unsupported(unsupported(UnsupportedErrorKind::GenericFeature(
+                    "interlaced".into(),
+                ))),
+                &png,
+                "Unsupported Codec",
+            ),
+            (
+                ImageError::Limits(LimitError::from_kind(LimitErrorKind::DimensionError)),
+                &png,
+                "Dimension Limit",
+            ),
+            (
+                ImageError::Decoding(DecodingError::new(hint.clone(), "invalid chunk")),
+                &png,
+                "Corrupted",
+            ),
+            // decoded by the format of the extension, the content of none
+            (
+                ImageError::Decoding(DecodingError::new(hint.clone(), "invalid signature")),
+                &unknown,
+                "Unknown Format",
+            ),
+            (
+                ImageError::Parameter(ParameterError::from_kind(
+                    ParameterErrorKind::DimensionMismatch,
+                )),
+                &png,
+                "Corrupted",
+            ),
+        ];
+        for (e, path, category) in xs.into_iter() {
+            let name = e.to_string();
+            assert_eq!(Error::decoding(path, e).category(), category, "{}", name);
+        }
+
+        // empty and tiny files, whatever the extension
+        for (name, content) in [
+            ("empty.jpg", ""),
+            ("empty.png", ""),
+            ("hi.jpg", "hi\n"),
+            ("hi.png", "hi\n"),
+            ("hi.bmp", "hi\n"),
+        ] {
+            let path = tmp.path().join(name);
+            std::fs::write(&path, content)?;
+            let e = crate::decode(&path).unwrap_err();
+            assert_eq!(
+                Error::decoding(&path, e).category(),
+                "Unknown Format",
+                "{}",
+                name
+            );
+        }
+        Ok(())
+    }
+}
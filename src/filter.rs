@@ -0,0 +1,214 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+/// Which files of a folder to load, applied by `load_files`.
+#[derive(clap::Args, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FilterArgs {
+    /// Load the files of a list instead of `-i`, one path per line or JSONL with a `path` field,
+    /// relative to the folder of the list, or the current one for stdin. `-` for stdin, the same as `-i -`
+    #[arg(long, value_name = "FILE")]
+    #[serde(default)]
+    pub list: Option<PathBuf>,
+
+    /// Load only the files matching any of the glob patterns, relative to the input folder
+    /// (the common folder of the files of a list), e.g. `**/*.jpg`
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Skip the files and folders matching any of the glob patterns, relative to the input folder
+    /// (the common folder of the files of a list), e.g. `labels`
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Load only the files with these extensions, case-insensitive, e.g. `jpg,png`
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub ext: Vec<String>,
+
+    /// Max depth of the folders traversed by `-r`, 1 for the files of the input folder only
+    #[arg(long)]
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+
+    /// Follow the symlinks, skipping the cycles and loading each real file once
+    #[arg(long)]
+    #[serde(default)]
+    pub follow_links: bool,
+}
+
+/// `FilterArgs` with the patterns compiled.
+pub(crate) struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    ext: Vec<String>,
+}
+
+fn globset(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for x in patterns.iter() {
+        builder.add(Glob::new(x).map_err(Error::Glob)?);
+    }
+    builder.build().map_err(Error::Glob)
+}
+
+impl FilterArgs {
+    /// Whether no file is filtered out.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty()
+            && self.exclude.is_empty()
+            && self.ext.is_empty()
+            && self.max_depth.is_none()
+    }
+
+    pub(crate) fn build(&self) -> Result<Filter, Error> {
+        Ok(Filter {
+            include: match self.include.is_empty() {
+                true => None,
+                false => Some(globset(&self.include)?),
+            },
+            exclude: globset(&self.exclude)?,
+            ext: self
+                .ext
+                .iter()
+                .map(|x| x.trim_start_matches('.').to_lowercase())
+                .collect(),
+        })
+    }
+}
+
+impl Filter {
+    /// Whether to skip the folder, `rel` is relative to the input folder.
+    pub fn excludes(&self, rel: &Path) -> bool {
+        self.exclude.is_match(rel)
+    }
+
+    /// Whether to load the file, `rel` is relative to the input folder.
+    pub fn matches(&self, rel: &Path) -> bool {
+        if self.excludes(rel) {
+            return false;
+        }
+        if let Some(include) = &self.include {
+            if !include.is_match(rel) {
+                return false;
+            }
+        }
+        if !self.ext.is_empty() {
+            let ext = rel
+                .extension()
+                .map(|x| x.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            if !self.ext.contains(&ext) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::walk;
+
+    /// Files walked by `-r` with `filter`, relative to `root`, sorted.
+    fn walked(root: &Path, filter: FilterArgs) -> Result<Vec<String>, Error> {
+        let mut ys: Vec<String> = walk(root, true, false, &filter)?
+            .files
+            .iter()
+            .map(|x| {
+                x.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect();
+        ys.sort();
+        Ok(ys)
+    }
+
+    #[test]
+    fn walk_filtered() -> Result<(), Error> {
+        let tmp = tempfile::tempdir()?;
+        let input = tmp.path().join("input");
+        let root = input.as_path();
+        for rel in [
+            "a.JPG",
+            "b.png",
+            "c.txt",
+            "sub/d.jpg",
+            "sub/deep/e.jpg",
+            "labels/f.jpg",
+        ] {
+            std::fs::create_dir_all(root.join(rel).parent().unwrap())?;
+            std::fs::write(root.join(rel), rel)?;
+        }
+        let all = walked(root, FilterArgs::default())?;
+        assert_eq!(all.len(), 6);
+
+        // globs relative to the input folder, excluded folders skipped as a whole
+        let include = vec!["sub/**".to_string()];
+        let ys = walked(
+            root,
+            FilterArgs {
+                include,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(ys, ["sub/d.jpg", "sub/deep/e.jpg"]);
+        let exclude = vec!["labels".to_string(), "*.txt".to_string()];
+        let ys = walked(
+            root,
+            FilterArgs {
+                exclude,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(ys, ["a.JPG", "b.png", "sub/d.jpg", "sub/deep/e.jpg"]);
+        let include = vec!["**/*.jpg".to_string()];
+        let exclude = vec!["sub/deep".to_string()];
+        let ys = walked(
+            root,
+            FilterArgs {
+                include,
+                exclude,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(ys, ["labels/f.jpg", "sub/d.jpg"]);
+
+        // extensions case-insensitive, with or without the leading dot
+        let ext = vec![".jpg".to_string(), "PNG".to_string()];
+        let ys = walked(
+            root,
+            FilterArgs {
+                ext,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(
+            ys,
+            [
+                "a.JPG",
+                "b.png",
+                "labels/f.jpg",
+                "sub/d.jpg",
+                "sub/deep/e.jpg"
+            ]
+        );
+
+        // 1 for the files of the input folder only
+        for (depth, n) in [(1, 3), (2, 5), (3, 6)] {
+            let filter = FilterArgs {
+                max_depth: Some(depth),
+                ..Default::default()
+            };
+            assert_eq!(walked(root, filter)?.len(), n, "max depth: {}", depth);
+        }
+        Ok(())
+    }
+}
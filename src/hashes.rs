@@ -0,0 +1,143 @@
+//! Perceptual hashes on the grayscale image, `side * side` bits packed MSB first.
+use image::{imageops::FilterType, DynamicImage, GrayImage};
+
+fn gray(img: &DynamicImage, w: u32, h: u32, filter: FilterType) -> GrayImage {
+    img.resize_exact(w, h, filter).to_luma8()
+}
+
+fn pack<I: IntoIterator<Item = bool>>(bits: I) -> Vec<u8> {
+    let mut ys: Vec<u8> = Vec::new();
+    for (i, bit) in bits.into_iter().enumerate() {
+        if i % 8 == 0 {
+            ys.push(0);
+        }
+        if bit {
+            *ys.last_mut().unwrap() |= 0x80 >> (i % 8);
+        }
+    }
+    ys
+}
+
+fn median(xs: &[f32]) -> f32 {
+    let mut xs = xs.to_vec();
+    xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = xs.len();
+    (xs[(n - 1) / 2] + xs[n / 2]) / 2.
+}
+
+/// aHash: pixels brighter than the mean.
+pub fn average_hash(img: &DynamicImage, side: u32) -> Vec<u8> {
+    let x = gray(img, side, side, FilterType::Triangle);
+    let mean = x.pixels().map(|p| p[0] as f32).sum::<f32>() / (side * side) as f32;
+    pack(x.pixels().map(|p| p[0] as f32 > mean))
+}
+
+/// dHash: pixels brighter than their left neighbors.
+pub fn difference_hash(img: &DynamicImage, side: u32) -> Vec<u8> {
+    let x = gray(img, side + 1, side, FilterType::Triangle);
+    pack((0..side).flat_map(|r| {
+        let x = &x;
+        (0..side).map(move |c| x.get_pixel(c + 1, r)[0] > x.get_pixel(c, r)[0])
+    }))
+}
+
+/// pHash: low frequencies of the 2D DCT-II of a `4 * side` image, above their median.
+pub fn perceptual_hash(img: &DynamicImage, side: u32) -> Vec<u8> {
+    let n = (side * 4) as usize;
+    let s = side as usize;
+    let x = gray(img, n as u32, n as u32, FilterType::Triangle);
+    let x = x.pixels().map(|p| p[0] as f32).collect::<Vec<f32>>();
+
+    // separable DCT-II, only the `side` lowest frequencies are needed
+    let cos = (0..s)
+        .map(|k| {
+            (0..n)
+                .map(|i| (std::f32::consts::PI / n as f32 * (i as f32 + 0.5) * k as f32).cos())
+                .collect::<Vec<f32>>()
+        })
+        .collect::<Vec<_>>();
+    let mut rows = vec![0f32; n * s];
+    for r in 0..n {
+        for k in 0..s {
+            rows[r * s + k] = (0..n).map(|c| x[r * n + c] * cos[k][c]).sum();
+        }
+    }
+    let mut coefs = vec![0f32; s * s];
+    for k in 0..s {
+        for c in 0..s {
+            coefs[k * s + c] = (0..n).map(|r| rows[r * s + c] * cos[k][r]).sum();
+        }
+    }
+
+    // the DC term is excluded from the median
+    let m = median(&coefs[1..]);
+    pack(coefs.iter().map(|&v| v > m))
+}
+
+/// wHash: Haar wavelet LL band of a `8 * side` image, above its median.
+pub fn wavelet_hash(img: &DynamicImage, side: u32) -> Vec<u8> {
+    let mut n = (side * 8) as usize;
+    let x = gray(img, n as u32, n as u32, FilterType::Triangle);
+    let mut ll = x.pixels().map(|p| p[0] as f32 / 255.).collect::<Vec<f32>>();
+    while n > side as usize {
+        let h = n / 2;
+        let mut y = vec![0f32; h * h];
+        for r in 0..h {
+            for c in 0..h {
+                y[r * h + c] = (ll[2 * r * n + 2 * c]
+                    + ll[2 * r * n + 2 * c + 1]
+                    + ll[(2 * r + 1) * n + 2 * c]
+                    + ll[(2 * r + 1) * n + 2 * c + 1])
+                    / 2.;
+            }
+        }
+        ll = y;
+        n = h;
+    }
+    let m = median(&ll);
+    pack(ll.iter().map(|&v| v > m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// Smooth blobs on a gradient, with enough low-frequency structure to hash.
+    fn sample(w: u32, h: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(w, h, |x, y| {
+            let (u, v) = (x as f32 / w as f32, y as f32 / h as f32);
+            let a = (u * 7.).sin() * (v * 5.).cos();
+            let b = ((u - 0.3).powi(2) + (v - 0.6).powi(2)).sqrt();
+            let g = (128. + 90. * a - 120. * b + 40. * u).clamp(0., 255.) as u8;
+            Rgb([g, g / 2, 255 - g])
+        }))
+    }
+
+    fn hamming(a: &[u8], b: &[u8]) -> u32 {
+        a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+    }
+
+    #[test]
+    fn bit_counts() {
+        let img = sample(200, 150);
+        for side in [8, 16] {
+            let bytes = (side * side / 8) as usize;
+            for hash in [average_hash, difference_hash, perceptual_hash, wavelet_hash] {
+                assert_eq!(hash(&img, side).len(), bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn stable_under_resizing() {
+        let img = sample(320, 240);
+        let small = img.resize_exact(160, 120, FilterType::Lanczos3);
+        let flipped = img.fliph();
+        for hash in [average_hash, difference_hash, perceptual_hash, wavelet_hash] {
+            let x = hash(&img, 8);
+            assert!(hamming(&x, &hash(&small, 8)) <= 4);
+            assert!(hamming(&x, &hash(&flipped, 8)) >= 16);
+        }
+    }
+}
@@ -0,0 +1,617 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::document::{Collection, Failed};
+use crate::retrival::{log_index, retrive, save_matched, text_embedder, Kind, Retrived};
+use crate::{
+    archive, canonical, checksum, decode_from, is_json, log_source, par_map, print_json, read_all,
+    stat, thresh_help, walk_all, Error, Extractor, FilterArgs, Method, NnArgs, SaveArgs, TextArgs,
+    Walked, LOGGER,
+};
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Build the index of a collection and save it
+    Build(BuildArgs),
+
+    /// Retrive images with a saved index
+    Query(QueryArgs),
+
+    /// Sync a saved index with the added, removed and modified files of its collection
+    Update(UpdateArgs),
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct BuildArgs {
+    /// Image or folder, repeatable for several, `-` to read the paths of the files from stdin
+    #[arg(short, long, required_unless_present = "list", conflicts_with = "list")]
+    input: Vec<String>,
+
+    /// Where to save the index, the manifest goes to `<INDEX>.json`
+    #[arg(long)]
+    index: String,
+
+    #[arg(short, long)]
+    recursive: bool,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[arg(long, value_enum, default_value_t = Method::BlockHash)]
+    method: Method,
+
+    /// Bits of the perceptual hashes, 256 for blockhash and 64 for the others by default
+    #[arg(long)]
+    bits: Option<usize>,
+
+    #[command(flatten)]
+    nn: NnArgs,
+
+    /// Number of threads, 0 for all the cores
+    #[arg(short, long, default_value_t = 0)]
+    jobs: usize,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct QueryArgs {
+    #[arg(long)]
+    index: String,
+
+    /// Query image, or query text for `--kind text`
+    #[arg(long)]
+    query: String,
+
+    #[arg(short, long, value_enum, default_value_t = Kind::Image)]
+    kind: Kind,
+
+    #[command(flatten)]
+    save: SaveArgs,
+
+    #[command(flatten)]
+    text: TextArgs,
+
+    #[arg(long, help = thresh_help())]
+    thresh: Option<f32>,
+
+    /// Number of the ranked results for `--kind text`
+    #[arg(long, default_value_t = 10)]
+    topk: usize,
+
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct UpdateArgs {
+    #[arg(long)]
+    index: String,
+
+    /// Collection to sync with, repeatable for several, defaults to the one the index was built from
+    #[arg(short, long)]
+    input: Vec<String>,
+
+    /// Number of threads, 0 for all the cores
+    #[arg(short, long, default_value_t = 0)]
+    jobs: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub key: u64,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub mtime: u64,
+    /// `None` for the files failed to read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<u64>,
+    /// Input the file came from, for more than one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+}
+
+/// Paths of the indexed images, saved next to the usearch index.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    #[serde(default)]
+    pub input: PathBuf,
+    /// All the inputs, for more than one
+    #[serde(default)]
+    pub inputs: Vec<PathBuf>,
+    /// Folder the files are relative to, for `--preserve-tree`
+    #[serde(default)]
+    pub root: PathBuf,
+    #[serde(default)]
+    pub recursive: bool,
+    /// Filters of the files of the collection
+    #[serde(default)]
+    pub filter: FilterArgs,
+    pub method: Method,
+    #[serde(default)]
+    pub bits: Option<usize>,
+    /// Options of the onnx model, for `--method nn`
+    #[serde(default)]
+    pub nn: Option<NnArgs>,
+    pub entries: Vec<Entry>,
+    /// Files failed to decode, kept to skip them until modified
+    #[serde(default)]
+    pub deprecated: Vec<Entry>,
+}
+
+impl Manifest {
+    pub fn path(index: &str) -> PathBuf {
+        PathBuf::from(format!("{}.json", index))
+    }
+
+    pub fn save(&self, index: &str) -> Result<()> {
+        let f = std::fs::File::create(Self::path(index))?;
+        serde_json::to_writer(std::io::BufWriter::new(f), self)?;
+        Ok(())
+    }
+
+    pub fn load(index: &str) -> Result<Self> {
+        let f = std::fs::File::open(Self::path(index))?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(f))?)
+    }
+
+    /// Load the manifest then the index it describes.
+    pub fn load_with_index(index: &str) -> Result<(Self, Extractor, usearch::Index)> {
+        let manifest = Self::load(index)?;
+        let extractor = Extractor::new(manifest.method, manifest.bits, manifest.nn.as_ref())?;
+        let x = usearch::Index::new(&extractor.index_options())?;
+        x.load(index)?;
+        Ok((manifest, extractor, x))
+    }
+}
+
+/// Add the files to the index with keys starting from `key0`, each read once for its feat and checksum.
+/// Returns the entries of the registered, then of the deprecated, failed to stat, read or decode, with the errors.
+/// The input of each file is looked up in `walked`, for more than one.
+#[allow(clippy::type_complexity)]
+fn register(
+    index: &usearch::Index,
+    paths: &[PathBuf],
+    key0: u64,
+    extractor: &Extractor,
+    walked: &Walked,
+    jobs: usize,
+) -> Result<(Vec<Entry>, Vec<(Entry, Error)>), Error> {
+    index
+        .reserve(index.size() + paths.len())
+        .map_err(Error::index)?;
+    let ys = par_map(paths, jobs, "Building", |p| {
+        let mut entry = Entry {
+            key: 0,
+            path: canonical(p).unwrap_or_else(|_| p.to_path_buf()),
+            size: 0,
+            mtime: 0,
+            checksum: None,
+            root: None,
+        };
+        let unreadable = |source| Error::Unreadable {
+            path: p.to_path_buf(),
+            source,
+        };
+        let buf = match stat(p) {
+            Err(e) => return Ok((entry, Err(e))),
+            Ok((size, mtime)) => {
+                (entry.size, entry.mtime) = (size, mtime);
+                match read_all(p) {
+                    Err(e) => return Ok((entry, Err(unreadable(e)))),
+                    Ok(buf) => buf,
+                }
+            }
+        };
+        entry.checksum = Some(xxhash_rust::xxh3::xxh3_64(&buf));
+        match decode_from(p, &buf) {
+            Err(e) => Ok((entry, Err(Error::decoding(p, e)))),
+            Ok(img) => extractor.extract(&img).map(|x| (entry, Ok(x))),
+        }
+    })?;
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut deprecated: Vec<(Entry, Error)> = Vec::new();
+    for (idx, y) in ys.into_iter().enumerate() {
+        let (mut entry, feat) = y?;
+        entry.key = key0 + idx as u64;
+        entry.root = walked.root_of(&entry.path);
+        match feat {
+            Err(e) => deprecated.push((entry, e)),
+            Ok(feat) => {
+                feat.register(index, entry.key)?;
+                entries.push(entry);
+            }
+        }
+    }
+    Ok((entries, deprecated))
+}
+
+/// Indexed or synced collection, for `--format json`.
+#[derive(Serialize, Debug)]
+struct Document<'a> {
+    command: &'static str,
+    #[serde(flatten)]
+    collection: Collection<'a>,
+    index: &'a str,
+    manifest: PathBuf,
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl Args {
+    pub fn run(&self) -> Result<()> {
+        match &self.command {
+            Command::Build(x) => x.run(),
+            Command::Query(x) => x.run(),
+            Command::Update(x) => x.run(),
+        }
+    }
+}
+
+impl BuildArgs {
+    pub fn run(&self) -> Result<()> {
+        let mut walked = walk_all(&self.input, self.recursive, false, &self.filter)?;
+        log_source("Collection", self.recursive, &self.filter, &walked)?;
+        let paths = std::mem::take(&mut walked.files);
+        let mut nn = self.nn.clone();
+        if let Some(model) = &nn.model {
+            nn.model = Some(model.canonicalize()?);
+        }
+        let extractor = Extractor::new(self.method, self.bits, Some(&nn))?;
+        let index = usearch::Index::new(&extractor.index_options()).map_err(Error::index)?;
+        let (entries, v_deprecated) = register(&index, &paths, 0, &extractor, &walked, self.jobs)?;
+        log_index(&index);
+        let doc = Document {
+            command: "index build",
+            collection: Collection::new(
+                &walked,
+                self.recursive,
+                v_deprecated
+                    .iter()
+                    .map(|(x, e)| Failed::new(&x.path, Some(e)))
+                    .collect(),
+            ),
+            index: &self.index,
+            manifest: Manifest::path(&self.index),
+            counts: BTreeMap::from([
+                ("indexed", entries.len()),
+                ("deprecated", v_deprecated.len()),
+            ]),
+        };
+        index.save(&self.index)?;
+        Manifest {
+            input: walked.source.to_path_buf(),
+            inputs: walked.roots.clone(),
+            root: walked.root.to_path_buf(),
+            recursive: self.recursive,
+            filter: FilterArgs {
+                list: walked.listed.then(|| walked.source.to_path_buf()),
+                ..self.filter.clone()
+            },
+            method: self.method,
+            bits: self.bits,
+            nn: match self.method {
+                Method::Nn => Some(nn),
+                _ => None,
+            },
+            entries,
+            deprecated: v_deprecated.into_iter().map(|x| x.0).collect(),
+        }
+        .save(&self.index)?;
+        LOGGER.success("Index saved to", &self.index, "");
+        LOGGER.success(
+            "Manifest saved to",
+            &format!("{}", Manifest::path(&self.index).display()),
+            "",
+        );
+        if is_json() {
+            print_json(&doc)?;
+        }
+        Ok(())
+    }
+}
+
+impl QueryArgs {
+    pub fn run(&self) -> Result<()> {
+        let (manifest, extractor, index) = Manifest::load_with_index(&self.index)?;
+        let embedder = text_embedder(&self.kind, &extractor, &self.text)?;
+        LOGGER.success("Index", &self.index, "");
+        LOGGER.success("", "Size", &format!("{}", index.size()));
+        let root = match manifest.root.as_os_str().is_empty() {
+            true => manifest.input.to_path_buf(),
+            false => manifest.root,
+        };
+        // the input of each file, for more than one
+        let walk = Walked {
+            roots: manifest.inputs.clone(),
+            origins: manifest
+                .entries
+                .iter()
+                .filter_map(|x| Some((x.path.to_path_buf(), x.root.clone()?)))
+                .collect(),
+            ..Default::default()
+        };
+        let paths: HashMap<u64, PathBuf> = manifest
+            .entries
+            .into_iter()
+            .map(|x| (x.key, x.path))
+            .collect();
+        LOGGER.success("Query", &self.query, "");
+        let v_matched = retrive(
+            &index,
+            &extractor,
+            embedder.as_ref(),
+            &self.query,
+            self.thresh,
+            self.topk,
+        )?
+        .into_iter()
+        .filter_map(|(k, d)| paths.get(&k).map(|p| (p.to_path_buf(), d)))
+        .collect::<Vec<_>>();
+        let _archives = archive::open_all(&v_matched.iter().map(|x| &x.0).collect::<Vec<_>>());
+        let retrived = Retrived {
+            query: &self.query,
+            kind: &self.kind,
+            collection: Collection {
+                input: manifest.input,
+                recursive: manifest.recursive,
+                walk: &walk,
+                deprecated: manifest
+                    .deprecated
+                    .iter()
+                    .map(|x| Failed::new(&x.path, None))
+                    .collect(),
+            },
+            matched: v_matched,
+            root: &root,
+        };
+        save_matched(retrived, &self.save, self.verbose)
+    }
+}
+
+impl UpdateArgs {
+    pub fn run(&self) -> Result<()> {
+        let (mut manifest, extractor, index) = Manifest::load_with_index(&self.index)?;
+        let inputs = match self.input.is_empty() {
+            false => {
+                manifest.filter.list = None;
+                self.input.iter().map(PathBuf::from).collect()
+            }
+            true if manifest.inputs.is_empty() => vec![manifest.input.clone()],
+            true => manifest.inputs.clone(),
+        };
+        let stdin = Some(Path::new("-"));
+        if self.input.is_empty()
+            && (manifest.filter.list.as_deref() == stdin
+                || inputs.iter().any(|x| Some(x.as_path()) == stdin))
+        {
+            anyhow::bail!("The index was built from stdin, use `-i` to give the collection")
+        }
+        let mut walked = walk_all(&inputs, manifest.recursive, false, &manifest.filter)?;
+        log_source("Collection", manifest.recursive, &manifest.filter, &walked)?;
+        let paths = std::mem::take(&mut walked.files);
+        let paths = paths.iter().map(canonical).collect::<Result<Vec<_>, _>>()?;
+
+        // compare with the manifest, size & mtime first, then the checksum
+        let key0 = manifest
+            .entries
+            .iter()
+            .chain(manifest.deprecated.iter())
+            .map(|x| x.key + 1)
+            .max()
+            .unwrap_or(0);
+        let mut olds: HashMap<PathBuf, Entry> = manifest
+            .entries
+            .drain(..)
+            .chain(manifest.deprecated.drain(..))
+            .map(|x| (x.path.clone(), x))
+            .collect();
+        let mut keeps: Vec<Entry> = Vec::new();
+        let mut v_added: Vec<PathBuf> = Vec::new();
+        let mut v_touched: Vec<(Entry, u64, u64)> = Vec::new();
+        let mut v_modified: Vec<PathBuf> = Vec::new();
+        for path in paths.iter() {
+            match olds.remove(path) {
+                None => v_added.push(path.to_path_buf()),
+                Some(old) => match stat(path) {
+                    Ok((size, mtime)) if old.size == size && old.mtime == mtime => keeps.push(old),
+                    Ok((size, mtime)) => v_touched.push((old, size, mtime)),
+                    // registered again, as deprecated
+                    Err(_) => {
+                        if index.contains(old.key) {
+                            index.remove(old.key)?;
+                        }
+                        v_modified.push(old.path);
+                    }
+                },
+            }
+        }
+        let checksums = par_map(
+            &v_touched
+                .iter()
+                .map(|x| x.0.path.clone())
+                .collect::<Vec<_>>(),
+            self.jobs,
+            "Checksumming",
+            |p| checksum(p),
+        )?;
+        for ((mut old, size, mtime), checksum) in v_touched.into_iter().zip(checksums) {
+            if old.checksum.is_some() && old.checksum == checksum.ok() {
+                old.size = size;
+                old.mtime = mtime;
+                keeps.push(old);
+            } else {
+                if index.contains(old.key) {
+                    index.remove(old.key)?;
+                }
+                v_modified.push(old.path);
+            }
+        }
+        let cnt_removed = olds.len();
+        for old in olds.into_values() {
+            if index.contains(old.key) {
+                index.remove(old.key)?;
+            }
+        }
+
+        // register the added & modified
+        let v_new = [v_added.as_slice(), v_modified.as_slice()].concat();
+        let (mut entries, v_failed) =
+            register(&index, &v_new, key0, &extractor, &walked, self.jobs)?;
+        let mut v_failed: Vec<(Entry, Option<Error>)> =
+            v_failed.into_iter().map(|(x, e)| (x, Some(e))).collect();
+        for mut x in keeps {
+            x.root = walked.root_of(&x.path);
+            if index.contains(x.key) {
+                entries.push(x);
+            } else {
+                v_failed.push((x, None));
+            }
+        }
+        v_failed.sort_by_key(|x| x.0.key);
+        let doc = Document {
+            command: "index update",
+            collection: Collection::new(
+                &walked,
+                manifest.recursive,
+                v_failed
+                    .iter()
+                    .map(|(x, e)| Failed::new(&x.path, e.as_ref()))
+                    .collect(),
+            ),
+            index: &self.index,
+            manifest: Manifest::path(&self.index),
+            counts: BTreeMap::from([
+                ("added", v_added.len()),
+                ("modified", v_modified.len()),
+                ("removed", cnt_removed),
+                ("deprecated", v_failed.len()),
+                ("indexed", index.size()),
+            ]),
+        };
+
+        // summary
+        LOGGER.success("Updated", "", "");
+        LOGGER.success("", "Added", &format!("x{}", v_added.len()));
+        LOGGER.success("", "Modified", &format!("x{}", v_modified.len()));
+        LOGGER.success("", "Removed", &format!("x{}", cnt_removed));
+        LOGGER.success("", "Size", &format!("{}", index.size()));
+
+        // save
+        entries.sort_by_key(|x| x.key);
+        manifest.input = walked.source.to_path_buf();
+        manifest.inputs = walked.roots.clone();
+        manifest.root = walked.root.to_path_buf();
+        manifest.entries = entries;
+        manifest.deprecated = v_failed.into_iter().map(|x| x.0).collect();
+        index.save(&self.index)?;
+        manifest.save(&self.index)?;
+        LOGGER.success("Index saved to", &self.index, "");
+        if is_json() {
+            print_json(&doc)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use image::{Rgb, RgbImage};
+
+    /// Uncompressed, so the images of the same dimensions have the same size.
+    fn save_bmp(path: &Path, seed: u32) -> Result<()> {
+        let img = RgbImage::from_fn(32, 32, |x, y| {
+            let v = ((x * seed + y * (seed + 3)) % 256) as u8;
+            Rgb([v, v / 2, 255 - v])
+        });
+        img.save(path)?;
+        Ok(())
+    }
+
+    fn keys(entries: &[Entry]) -> HashMap<String, u64> {
+        entries
+            .iter()
+            .map(|x| (x.path.file_name().unwrap().to_string_lossy().into(), x.key))
+            .collect()
+    }
+
+    #[test]
+    fn update_added_removed_modified() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path().join("images");
+        std::fs::create_dir(&dir)?;
+        for (name, seed) in [("a.bmp", 1), ("b.bmp", 2), ("c.bmp", 3)] {
+            save_bmp(&dir.join(name), seed)?;
+        }
+        std::fs::write(dir.join("bad.jpg"), "not an image")?;
+        let index = tmp.path().join("x.usearch").to_string_lossy().to_string();
+        let dir_ = dir.to_string_lossy().to_string();
+        BuildArgs::parse_from(["build", "-i", &dir_, "--index", &index]).run()?;
+        let built = Manifest::load(&index)?;
+        assert_eq!(built.entries.len(), 3);
+        assert_eq!(built.deprecated.len(), 1);
+        assert!(built.deprecated[0].checksum.is_some());
+
+        // same size and dimensions, new content, with an older mtime
+        let size = std::fs::metadata(dir.join("b.bmp"))?.len();
+        save_bmp(&dir.join("b.bmp"), 7)?;
+        assert_eq!(std::fs::metadata(dir.join("b.bmp"))?.len(), size);
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("b.bmp"))?
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1))?;
+        std::fs::remove_file(dir.join("c.bmp"))?;
+        save_bmp(&dir.join("d.bmp"), 4)?;
+
+        UpdateArgs::parse_from(["update", "--index", &index]).run()?;
+        let (updated, _, x) = Manifest::load_with_index(&index)?;
+        let (olds, news) = (keys(&built.entries), keys(&updated.entries));
+        let mut names = news.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a.bmp", "b.bmp", "d.bmp"]);
+        assert_eq!(news["a.bmp"], olds["a.bmp"]);
+        assert_ne!(news["b.bmp"], olds["b.bmp"]);
+        assert!(!x.contains(olds["b.bmp"]) && !x.contains(olds["c.bmp"]));
+        assert_eq!(x.size(), 3);
+        assert_eq!(keys(&updated.deprecated), keys(&built.deprecated));
+        Ok(())
+    }
+
+    #[test]
+    fn entries_with_inputs() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let (x, y) = (tmp.path().join("x"), tmp.path().join("y"));
+        for (dir, seed) in [(&x, 1), (&y, 2)] {
+            std::fs::create_dir(dir)?;
+            save_bmp(&dir.join(format!("{}.bmp", seed)), seed)?;
+        }
+        let index = tmp.path().join("x.usearch").to_string_lossy().to_string();
+        let (x_, y_) = (x.to_string_lossy(), y.to_string_lossy());
+        let argv = ["build", "-i", &x_, "-i", &y_, "--index", &index];
+        BuildArgs::parse_from(argv).run()?;
+
+        // kept by update, for the files not modified
+        let roots = |m: &Manifest| -> Vec<_> { m.entries.iter().map(|x| x.root.clone()).collect() };
+        let built = Manifest::load(&index)?;
+        assert_eq!(
+            roots(&built),
+            [Some(x.canonicalize()?), Some(y.canonicalize()?)]
+        );
+        UpdateArgs::parse_from(["update", "--index", &index]).run()?;
+        assert_eq!(roots(&Manifest::load(&index)?), roots(&built));
+
+        // none for a single input
+        BuildArgs::parse_from(["build", "-i", &x_, "--index", &index]).run()?;
+        assert_eq!(roots(&Manifest::load(&index)?), [None]);
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{build_pb, is_json, print_json, src2dst, Action, Error, Transfer, JOURNAL, LOGGER};
+
+#[derive(clap::Parser, Debug)]
+pub struct Args {
+    /// Journal of the moves, `.ilytix-journal.jsonl` in the output folder
+    journal: PathBuf,
+}
+
+/// Moves done in an output folder, one JSON line each, appended as they are done.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    pub path: PathBuf,
+}
+
+impl Journal {
+    pub fn new<P: AsRef<Path>>(saveout: P) -> Self {
+        Self {
+            path: saveout.as_ref().join(JOURNAL),
+        }
+    }
+
+    pub fn append(&self, action: &Action) -> Result<(), Error> {
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let line = serde_json::to_string(action).map_err(std::io::Error::from)?;
+        writeln!(f, "{}", line)?;
+        Ok(())
+    }
+
+    pub fn load(&self) -> Result<Vec<Action>> {
+        let s = std::fs::read_to_string(&self.path)?;
+        s.lines()
+            .enumerate()
+            .filter(|(_, x)| !x.trim().is_empty())
+            .map(|(i, x)| {
+                serde_json::from_str(x)
+                    .with_context(|| format!("{}, line {}", self.path.display(), i + 1))
+            })
+            .collect()
+    }
+
+    /// Keep the given moves only, removing the journal if none left.
+    fn save(&self, actions: &[&Action]) -> Result<()> {
+        if actions.is_empty() {
+            std::fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        let mut f = std::io::BufWriter::new(std::fs::File::create(&self.path)?);
+        for x in actions.iter() {
+            writeln!(f, "{}", serde_json::to_string(x)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl Args {
+    pub fn run(&self) -> Result<()> {
+        let journal = Journal {
+            path: self.journal.to_path_buf(),
+        };
+        let actions = journal.load()?;
+        LOGGER.success("Journal", &format!("{}", self.journal.display()), "");
+        let pb = build_pb(actions.len() as u64, "Restoring");
+        let mut cnt_restored = 0;
+        let mut v_conflicts: Vec<(&Action, String)> = Vec::new();
+
+        // latest first, for the files moved more than once
+        for x in actions.iter().rev() {
+            pb.inc(1);
+            if x.action != Transfer::Move {
+                continue;
+            }
+            if x.dst.symlink_metadata().is_err() {
+                v_conflicts.push((x, "Moved file not exist".to_string()));
+            } else if x.src.symlink_metadata().is_ok() {
+                v_conflicts.push((x, "Original path taken".to_string()));
+            } else {
+                let y = match x.src.parent() {
+                    Some(parent) => std::fs::create_dir_all(parent).map_err(Error::from),
+                    None => Ok(()),
+                }
+                .and_then(|_| src2dst(&x.dst, &x.src, Transfer::Move));
+                match y {
+                    Ok(()) => cnt_restored += 1,
+                    Err(e) => v_conflicts.push((x, e.to_string())),
+                }
+            }
+        }
+        pb.finish();
+
+        // keep the conflicts to retry
+        v_conflicts.reverse();
+        journal.save(&v_conflicts.iter().map(|x| x.0).collect::<Vec<_>>())?;
+
+        // summary
+        LOGGER.success("Restored", &format!("x{}", cnt_restored), "");
+        if !v_conflicts.is_empty() {
+            LOGGER.warn(
+                "Conflicts",
+                &format!("x{}", v_conflicts.len()),
+                "Kept in the journal",
+            );
+            for (x, reason) in v_conflicts.iter() {
+                LOGGER.warn("", &format!("{}", x.dst.display()), reason);
+            }
+        }
+        if is_json() {
+            print_json(&serde_json::json!({
+                "command": "undo",
+                "journal": self.journal,
+                "restored": cnt_restored,
+                "conflicts": v_conflicts
+                    .iter()
+                    .map(|(x, reason)| serde_json::json!({ "action": x, "reason": reason }))
+                    .collect::<Vec<_>>(),
+            }))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Plan;
+
+    #[test]
+    fn move_then_undo() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let src = tmp.path().join("src");
+        std::fs::create_dir(&src)?;
+        for name in ["a.txt", "b.txt"] {
+            std::fs::write(src.join(name), name)?;
+        }
+
+        // moved, each in the journal
+        let mut plan = Plan::new(Transfer::Move, None);
+        let saveout = plan.folder(tmp.path().join("out"))?;
+        for name in ["a.txt", "b.txt"] {
+            plan.save(src.join(name), name, None, &saveout, "Curated")?;
+        }
+        let journal = Journal::new(&saveout);
+        assert_eq!(journal.load()?.len(), 2);
+        assert!(!src.join("a.txt").exists() && saveout.join("a.txt").exists());
+
+        // the original path of `b.txt` taken meanwhile, kept in the journal
+        std::fs::write(src.join("b.txt"), "new")?;
+        let args = Args {
+            journal: journal.path.clone(),
+        };
+        args.run()?;
+        assert_eq!(std::fs::read_to_string(src.join("a.txt"))?, "a.txt");
+        assert_eq!(std::fs::read_to_string(src.join("b.txt"))?, "new");
+        let left = journal.load()?;
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].dst, saveout.join("b.txt"));
+
+        // retried once the conflict is resolved, the journal removed
+        std::fs::remove_file(src.join("b.txt"))?;
+        args.run()?;
+        assert_eq!(std::fs::read_to_string(src.join("b.txt"))?, "b.txt");
+        assert!(!journal.path.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+
+use crate::{canonical, stat, Error};
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq)]
+pub enum Keep {
+    /// Largest file size
+    Largest,
+    /// Most pixels
+    Resolution,
+    /// Most preferred format of `--formats`
+    Format,
+    /// Oldest modification time
+    Oldest,
+    /// Newest modification time
+    Newest,
+    /// Shortest path
+    Shortest,
+    /// First matched directory of `--priority`
+    Priority,
+}
+
+/// Default `--formats`, in descending preference.
+const FORMATS: &str = "png,tiff,tif,bmp,webp,jpeg,jpg,gif";
+
+/// Policy choosing the survivor of a group of duplicates.
+#[derive(clap::Args, Debug, Clone)]
+pub struct KeepArgs {
+    /// Which one of the duplicates survives, ties are broken by file size then path
+    #[arg(long, value_enum, default_value_t = Keep::Largest)]
+    pub keep: Keep,
+
+    /// Directories in descending priority, for `--keep priority`
+    #[arg(long, value_delimiter = ',')]
+    pub priority: Vec<PathBuf>,
+
+    /// File extensions in descending preference, for `--keep format`
+    #[arg(long, value_delimiter = ',', default_value = FORMATS)]
+    pub formats: Vec<String>,
+}
+
+impl Default for KeepArgs {
+    fn default() -> Self {
+        Self {
+            keep: Keep::Largest,
+            priority: Vec::new(),
+            formats: FORMATS.split(',').map(String::from).collect(),
+        }
+    }
+}
+
+impl KeepArgs {
+    /// Canonicalize the directories of `--priority`.
+    pub fn prepared(&self) -> Result<Self, Error> {
+        let mut x = self.clone();
+        if x.keep == Keep::Priority {
+            if x.priority.is_empty() {
+                return Err(Error::Options(
+                    "Use `--priority <DIR,...>` to set the directories for `--keep priority`"
+                        .into(),
+                ));
+            }
+            x.priority = x
+                .priority
+                .iter()
+                .map(|p| p.canonicalize())
+                .collect::<std::io::Result<Vec<_>>>()?;
+        }
+        Ok(x)
+    }
+
+    /// Rank of a file, the greater the better.
+    pub fn rank<P: AsRef<Path>>(&self, path: P, dims: (u32, u32)) -> Result<(i128, u64), Error> {
+        let path = path.as_ref();
+        let (size, mtime) = stat(path)?;
+        let nth = |i: Option<usize>, n: usize| -(i.unwrap_or(n) as i128);
+        let score = match self.keep {
+            Keep::Largest => size as i128,
+            Keep::Resolution => dims.0 as i128 * dims.1 as i128,
+            Keep::Format => {
+                let ext = path
+                    .extension()
+                    .and_then(|x| x.to_str())
+                    .map(|x| x.to_lowercase());
+                let i = self
+                    .formats
+                    .iter()
+                    .position(|x| Some(x.to_lowercase()) == ext);
+                nth(i, self.formats.len())
+            }
+            Keep::Oldest | Keep::Newest => {
+                if self.keep == Keep::Oldest {
+                    -(mtime as i128)
+                } else {
+                    mtime as i128
+                }
+            }
+            Keep::Shortest => -(path.as_os_str().len() as i128),
+            Keep::Priority => {
+                let path = canonical(path)?;
+                let i = self.priority.iter().position(|x| path.starts_with(x));
+                nth(i, self.priority.len())
+            }
+        };
+        Ok((score, size))
+    }
+
+    /// Index of the survivor.
+    pub fn best<P: AsRef<Path>>(&self, xs: &[(P, (u32, u32))]) -> Result<usize, Error> {
+        let mut best: Option<(usize, (i128, u64))> = None;
+        for (i, (p, dims)) in xs.iter().enumerate() {
+            let rank = self.rank(p, *dims)?;
+            best = match best {
+                Some((j, r)) => {
+                    let better = rank > r || (rank == r && p.as_ref() < xs[j].0.as_ref());
+                    if better {
+                        Some((i, rank))
+                    } else {
+                        Some((j, r))
+                    }
+                }
+                None => Some((i, rank)),
+            };
+        }
+        Ok(best.map(|x| x.0).unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `best` of `xs` under `args`, as the file name.
+    fn best(args: &KeepArgs, xs: &[(PathBuf, (u32, u32))]) -> Result<String, Error> {
+        let i = args.prepared()?.best(xs)?;
+        Ok(xs[i].0.file_name().unwrap().to_string_lossy().into())
+    }
+
+    #[test]
+    fn keep_policies() -> Result<(), Error> {
+        let tmp = tempfile::tempdir()?;
+        let (a, b) = (tmp.path().join("a"), tmp.path().join("b"));
+        std::fs::create_dir_all(a.join("deep"))?;
+        std::fs::create_dir_all(&b)?;
+        // size, mtime in secs
+        let files = [
+            (a.join("deep/long-name.jpg"), 30, 100),
+            (a.join("x.png"), 10, 300),
+            (b.join("y.jpg"), 20, 200),
+        ];
+        for (p, size, mtime) in files.iter() {
+            std::fs::write(p, vec![0u8; *size])?;
+            std::fs::File::options()
+                .write(true)
+                .open(p)?
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(*mtime))?;
+        }
+        let dims = [(10, 10), (20, 20), (15, 15)];
+        let xs: Vec<(PathBuf, (u32, u32))> = files
+            .iter()
+            .zip(dims)
+            .map(|(x, d)| (x.0.clone(), d))
+            .collect();
+        let args = |keep| KeepArgs {
+            keep,
+            ..Default::default()
+        };
+
+        assert_eq!(best(&args(Keep::Largest), &xs)?, "long-name.jpg");
+        assert_eq!(best(&args(Keep::Resolution), &xs)?, "x.png");
+        assert_eq!(best(&args(Keep::Format), &xs)?, "x.png");
+        assert_eq!(best(&args(Keep::Oldest), &xs)?, "long-name.jpg");
+        assert_eq!(best(&args(Keep::Newest), &xs)?, "x.png");
+        // `a/x.png` as short as `b/y.jpg`, the larger
+        assert_eq!(best(&args(Keep::Shortest), &xs)?, "y.jpg");
+        let priority = |dirs: &[&Path]| KeepArgs {
+            priority: dirs.iter().map(|x| x.to_path_buf()).collect(),
+            ..args(Keep::Priority)
+        };
+        // the first matched directory, then the largest within it
+        assert_eq!(best(&priority(&[&b, &a]), &xs)?, "y.jpg");
+        assert_eq!(best(&priority(&[&a, &b]), &xs)?, "long-name.jpg");
+        assert_eq!(best(&priority(&[&a.join("deep")]), &xs)?, "long-name.jpg");
+        assert!(matches!(
+            args(Keep::Priority).prepared(),
+            Err(Error::Options(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn keep_ties_by_size_then_path() -> Result<(), Error> {
+        let tmp = tempfile::tempdir()?;
+        let mut xs: Vec<(PathBuf, (u32, u32))> = Vec::new();
+        for (name, size) in [("c.jpg", 10), ("b.jpg", 20), ("a.jpg", 20)] {
+            let p = tmp.path().join(name);
+            std::fs::write(&p, vec![0u8; size])?;
+            xs.push((p, (10, 10)));
+        }
+        // the same resolution, the larger size, then the first path, whatever the order
+        let args = KeepArgs {
+            keep: Keep::Resolution,
+            ..Default::default()
+        };
+        assert_eq!(best(&args, &xs)?, "a.jpg");
+        xs.reverse();
+        assert_eq!(best(&args, &xs)?, "a.jpg");
+        xs.rotate_left(1);
+        assert_eq!(best(&args, &xs)?, "a.jpg");
+        Ok(())
+    }
+}
@@ -1,14 +1,38 @@
+mod archive;
+mod captioner;
 mod cli;
 mod deduplicator;
+mod document;
+mod error;
+mod filter;
+mod hashes;
 mod image_files;
+mod indexer;
+mod journal;
+mod keep;
 mod method;
+mod nn;
+mod plan;
 mod retrival;
 mod sanitizer;
 mod utils;
 
-pub use cli::{Cli, Task};
+pub use archive::{is_archive, is_member};
+pub use captioner::{CaptionArgs, Captioner};
+pub use cli::{Cli, Format, Task};
+pub use deduplicator::{
+    deduplicate, deduplicate_against, DedupOptions, Deduplication, Group, Member, Pair, Status,
+};
+pub use error::Error;
+pub use filter::FilterArgs;
 pub use image_files::ImageFiles;
-pub use method::Method;
+pub use indexer::Entry;
+pub use journal::Journal;
+pub use keep::{Keep, KeepArgs};
+pub use method::{Extractor, Feat, Method};
+pub use nn::{Embedder, NnArgs, TextArgs, TextEmbedder};
+pub use plan::{Action, Plan, SaveArgs};
+pub use retrival::{build_index, retrive, text_embedder, Kind};
 pub use utils::*;
 
 const LOGGER: Logger = Logger;
@@ -17,5 +41,7 @@ const SAVEOUT_RECTIFIED: &str = "Rectified";
 const SAVEOUT_VALID: &str = "Intact";
 const SAVEOUT_DEPRECATED: &str = "Deprecated Or Unsupported";
 const SAVEOUT_DUPLICATED: &str = "Duplicated";
+const SAVEOUT_IDENTICAL: &str = "Identical";
 const SAVEOUT_CURATED: &str = "Curated";
 const SAVEOUT_FILTERED: &str = "Filtered";
+const JOURNAL: &str = ".ilytix-journal.jsonl";
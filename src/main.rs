@@ -1,23 +1,31 @@
-use anyhow::Result;
 use clap::Parser;
 
-use ilytix::{Cli, Task};
+use ilytix::{set_json, set_progress, Cli, Error, Format, Logger, Task};
 
-fn main() -> Result<()> {
+fn main() {
     let cli = Cli::parse();
-    match &cli.task {
-        Task::Check(x) => {
-            x.run()?;
-        }
-        Task::Dedup(x) => {
-            x.run()?;
-        }
-        Task::Retrive(x) => {
-            x.run()?;
-        }
-        _ => {
-            todo!()
+    set_json(cli.format == Format::Json);
+    set_progress(cli.format == Format::Text);
+    let y = match &cli.task {
+        Task::Check(x) => x.run(),
+        Task::Dedup(x) => x.run(),
+        Task::Retrive(x) => x.run(),
+        Task::Index(x) => x.run(),
+        Task::Caption(x) => x.run(),
+        Task::Apply(x) => x.run(),
+        Task::Undo(x) => x.run(),
+    };
+    if let Err(e) = y {
+        match e.downcast_ref::<Error>() {
+            Some(Error::NoMatch) => {
+                Logger.exit("No image retrived", "--thresh", "Try a larger one")
+            }
+            Some(Error::NoOutput) => Logger.exit(
+                "Results",
+                "Not Saving",
+                "Use `-o <PATH>` to set the save location",
+            ),
+            _ => Logger.exit("Error", &format!("{:#}", e), ""),
         }
     }
-    Ok(())
 }
@@ -1,5 +1,152 @@
-#[derive(Debug, Clone, clap::ValueEnum, Copy)]
+use usearch::b1x8;
+use usearch::ffi::{IndexOptions, MetricKind, ScalarKind};
+
+use crate::hashes::{average_hash, difference_hash, perceptual_hash, wavelet_hash};
+use crate::{search_radius, Embedder, Error, NnArgs};
+
+#[derive(Debug, Clone, clap::ValueEnum, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum Method {
+    #[value(alias = "ahash")]
+    AverageHash,
+    #[value(alias = "dhash")]
+    DifferenceHash,
+    #[value(alias = "phash")]
+    PerceptualHash,
+    #[value(alias = "whash")]
+    WaveletHash,
     BlockHash,
     Nn,
 }
+
+impl Method {
+    /// Default `--bits` of the hashes.
+    pub fn bits(&self) -> usize {
+        match self {
+            Method::BlockHash => 256,
+            _ => 64,
+        }
+    }
+}
+
+/// Feature of one image, stored in a usearch index.
+pub enum Feat {
+    Bits(Vec<u8>),
+    Embedding(Vec<f32>),
+}
+
+impl Feat {
+    /// Distance in the metric of the index, Hamming for hashes and cosine for embeddings.
+    pub fn distance(&self, other: &Feat) -> f32 {
+        match (self, other) {
+            (Feat::Bits(a), Feat::Bits(b)) => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x ^ y).count_ones())
+                .sum::<u32>() as f32,
+            (Feat::Embedding(a), Feat::Embedding(b)) => {
+                1. - a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>()
+            }
+            _ => f32::NAN,
+        }
+    }
+
+    pub fn register(&self, index: &usearch::Index, key: u64) -> Result<(), Error> {
+        match self {
+            Feat::Bits(x) => index.add(key, b1x8::from_u8s(x)),
+            Feat::Embedding(x) => index.add(key, x),
+        }
+        .map_err(Error::index)
+    }
+
+    pub fn search(&self, index: &usearch::Index, radius: f32) -> Result<Vec<(u64, f32)>, Error> {
+        match self {
+            Feat::Bits(x) => search_radius(index, b1x8::from_u8s(x), radius),
+            Feat::Embedding(x) => search_radius(index, x, radius),
+        }
+    }
+}
+
+/// Candidates of the HNSW search of the large indexes, 64 by default of usearch, raised for recall.
+const EXPANSION_SEARCH: usize = 256;
+
+/// Feature extractor of the given `Method`.
+pub enum Extractor {
+    /// Perceptual hash with its number of bits
+    Hash(Method, usize),
+    Nn(Box<Embedder>),
+}
+
+impl Extractor {
+    pub fn new(method: Method, bits: Option<usize>, nn: Option<&NnArgs>) -> Result<Self, Error> {
+        let bits = bits.unwrap_or(method.bits());
+        let invalid = |x: &str| Err(Error::Options(x.into()));
+        Ok(match method {
+            Method::Nn => match nn {
+                None => return invalid("No onnx model options for `--method nn`"),
+                Some(nn) => Self::Nn(Box::new(Embedder::new(nn)?)),
+            },
+            Method::BlockHash => match bits {
+                16 | 64 | 144 | 256 => Self::Hash(method, bits),
+                _ => return invalid("`--bits` of blockhash expects 16, 64, 144 or 256"),
+            },
+            _ => {
+                let side = (bits as f64).sqrt() as usize;
+                if side < 2 || side * side != bits {
+                    return invalid("`--bits` expects a square number, like 64 or 256");
+                }
+                Self::Hash(method, bits)
+            }
+        })
+    }
+
+    /// Default `--thresh`, hamming distance for hashes and cosine distance for embeddings.
+    pub fn thresh(&self) -> f32 {
+        match self {
+            Self::Hash(Method::BlockHash, _) => 3.0,
+            Self::Hash(_, bits) => (*bits as f32 / 16.).round(),
+            Self::Nn(_) => 0.05,
+        }
+    }
+
+    pub fn index_options(&self) -> IndexOptions {
+        match self {
+            // bits packed as `b1x8`, so the hamming distance equals the one of the hashes
+            Self::Hash(_, bits) => IndexOptions {
+                dimensions: bits.div_ceil(8) * 8,
+                metric: MetricKind::Hamming,
+                quantization: ScalarKind::B1,
+                expansion_search: EXPANSION_SEARCH,
+                ..Default::default()
+            },
+            Self::Nn(embedder) => IndexOptions {
+                dimensions: embedder.dimensions(),
+                metric: MetricKind::Cos,
+                quantization: ScalarKind::F32,
+                expansion_search: EXPANSION_SEARCH,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn extract(&self, img: &image::DynamicImage) -> Result<Feat, Error> {
+        Ok(match self {
+            Self::Nn(embedder) => Feat::Embedding(embedder.embed(img)?),
+            Self::Hash(method, bits) => {
+                let side = (*bits as f64).sqrt() as u32;
+                Feat::Bits(match method {
+                    Method::AverageHash => average_hash(img, side),
+                    Method::DifferenceHash => difference_hash(img, side),
+                    Method::PerceptualHash => perceptual_hash(img, side),
+                    Method::WaveletHash => wavelet_hash(img, side),
+                    Method::BlockHash => match bits {
+                        16 => <[u8; 2]>::from(blockhash::blockhash16(img)).to_vec(),
+                        64 => <[u8; 8]>::from(blockhash::blockhash64(img)).to_vec(),
+                        144 => <[u8; 18]>::from(blockhash::blockhash144(img)).to_vec(),
+                        _ => <[u8; 32]>::from(blockhash::blockhash256(img)).to_vec(),
+                    },
+                    Method::Nn => unreachable!(),
+                })
+            }
+        })
+    }
+}
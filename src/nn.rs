@@ -0,0 +1,218 @@
+use anyhow::Result;
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use std::path::{Path, PathBuf};
+use tract_onnx::prelude::*;
+
+use crate::Error;
+
+fn l2_normalized(y: &TValue) -> Result<Vec<f32>> {
+    let mut y = y
+        .to_array_view::<f32>()?
+        .iter()
+        .copied()
+        .collect::<Vec<f32>>();
+    let norm = y.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0. {
+        y.iter_mut().for_each(|x| *x /= norm);
+    }
+    Ok(y)
+}
+
+/// Resize the short side then center crop, normalized to a `[1, 3, size, size]` tensor.
+pub(crate) fn preprocess(img: &DynamicImage, size: u32, mean: &[f32; 3], std: &[f32; 3]) -> Tensor {
+    let img = img.resize_to_fill(size, size, FilterType::CatmullRom);
+    let s = size as usize;
+    tract_ndarray::Array4::from_shape_fn((1, 3, s, s), |(_, c, y, x)| {
+        let v = img.get_pixel(x as u32, y as u32)[c] as f32 / 255.;
+        (v - mean[c]) / std[c]
+    })
+    .into()
+}
+
+pub(crate) fn per_channel(mean: &[f32], std: &[f32]) -> Result<([f32; 3], [f32; 3]), Error> {
+    if mean.len() != 3 || std.len() != 3 {
+        return Err(Error::Options(
+            "`--mean` and `--std` expect 3 values, one per channel".into(),
+        ));
+    }
+    Ok(([mean[0], mean[1], mean[2]], [std[0], std[1], std[2]]))
+}
+
+/// Options of the onnx image encoder used by `--method nn`.
+#[derive(clap::Args, Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NnArgs {
+    /// Onnx image encoder, e.g. the vision model of CLIP or DINO. The first output is the embedding
+    #[arg(long)]
+    pub model: Option<PathBuf>,
+
+    /// Input size of the image encoder
+    #[arg(long, default_value_t = 224)]
+    pub image_size: u32,
+
+    /// Per-channel mean for normalization
+    #[arg(long, value_delimiter = ',', default_values_t = [0.48145466f32, 0.4578275, 0.40821073])]
+    pub mean: Vec<f32>,
+
+    /// Per-channel std for normalization
+    #[arg(long, value_delimiter = ',', default_values_t = [0.26862954f32, 0.2613026, 0.2757771])]
+    pub std: Vec<f32>,
+}
+
+/// Onnx image encoder running on CPU.
+pub struct Embedder {
+    path: PathBuf,
+    model: TypedRunnableModel<TypedModel>,
+    size: u32,
+    mean: [f32; 3],
+    std: [f32; 3],
+    dimensions: usize,
+}
+
+impl Embedder {
+    pub fn new(args: &NnArgs) -> Result<Self, Error> {
+        let path = match &args.model {
+            None => {
+                return Err(Error::Options(
+                    "Use `--model <PATH>` to set the onnx model for `--method nn`".into(),
+                ))
+            }
+            Some(path) => path,
+        };
+        let (mean, std) = per_channel(&args.mean, &args.std)?;
+        let size = args.image_size;
+        let model = tract_onnx::onnx()
+            .model_for_path(path)
+            .and_then(|x| {
+                x.with_input_fact(0, f32::fact([1, 3, size as usize, size as usize]).into())
+            })
+            .and_then(|x| x.into_optimized())
+            .and_then(|x| x.into_runnable())
+            .map_err(|e| Error::model(path, e))?;
+        let mut embedder = Self {
+            path: path.to_path_buf(),
+            model,
+            size,
+            mean,
+            std,
+            dimensions: 0,
+        };
+
+        // dry run for the dimensions
+        embedder.dimensions = embedder.embed(&DynamicImage::new_rgb8(size, size))?.len();
+        Ok(embedder)
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    pub fn preprocess(&self, img: &DynamicImage) -> Tensor {
+        preprocess(img, self.size, &self.mean, &self.std)
+    }
+
+    /// L2-normalized embedding of the image.
+    pub fn embed(&self, img: &DynamicImage) -> Result<Vec<f32>, Error> {
+        self.model
+            .run(tvec!(self.preprocess(img).into()))
+            .and_then(|ys| l2_normalized(&ys[0]))
+            .map_err(|e| Error::model(&self.path, e))
+    }
+}
+
+/// Options of the onnx text encoder used by `--kind text`.
+#[derive(clap::Args, Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TextArgs {
+    /// Onnx text encoder paired with the image encoder of `--model`, e.g. the text model of CLIP
+    #[arg(long)]
+    pub text_model: Option<PathBuf>,
+
+    /// Tokenizer of the text encoder, a `tokenizer.json` of HuggingFace tokenizers
+    #[arg(long)]
+    pub tokenizer: Option<PathBuf>,
+
+    /// Tokens the text encoder takes, padded with 0 or truncated, at least 1
+    #[arg(
+        long,
+        default_value_t = 77,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub context_length: usize,
+}
+
+/// Onnx text encoder running on CPU, with `input_ids` (and `attention_mask`) as inputs.
+pub struct TextEmbedder {
+    path: PathBuf,
+    model: TypedRunnableModel<TypedModel>,
+    tokenizer: tokenizers::Tokenizer,
+    context_length: usize,
+}
+
+impl TextEmbedder {
+    pub fn new(args: &TextArgs) -> Result<Self, Error> {
+        let (path, tokenizer) =
+            match (&args.text_model, &args.tokenizer) {
+                (Some(path), Some(tokenizer)) => (path, tokenizer),
+                _ => return Err(Error::Options(
+                    "Use `--text-model <PATH>` and `--tokenizer <PATH>` to set the text encoder"
+                        .into(),
+                )),
+            };
+        let tokenizer =
+            tokenizers::Tokenizer::from_file(tokenizer).map_err(|e| Error::model(tokenizer, e))?;
+        let l = args.context_length;
+        if l == 0 {
+            return Err(Error::Options(
+                "`--context-length` expects at least 1".into(),
+            ));
+        }
+        let model = (|| {
+            let mut model = tract_onnx::onnx().model_for_path(path)?;
+            for i in 0..model.inputs.len() {
+                model = model.with_input_fact(i, i64::fact([1, l]).into())?;
+            }
+            model.into_optimized()?.into_runnable()
+        })()
+        .map_err(|e| Error::model(path, e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            model,
+            tokenizer,
+            context_length: l,
+        })
+    }
+
+    /// Path of the text encoder.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// L2-normalized embedding of the text.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>, Error> {
+        self.encode(text).map_err(|e| Error::model(&self.path, e))
+    }
+
+    fn encode(&self, text: &str) -> Result<Vec<f32>> {
+        let encoding = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(anyhow::Error::msg)?;
+        let mut ids = vec![0i64; self.context_length];
+        let mut mask = vec![0i64; self.context_length];
+        let tokens = encoding.get_ids();
+        for (i, &id) in tokens.iter().take(self.context_length).enumerate() {
+            ids[i] = id as i64;
+            mask[i] = 1;
+        }
+        // keep the end-of-text token when truncated
+        if tokens.len() > self.context_length {
+            ids[self.context_length - 1] = *tokens.last().unwrap() as i64;
+        }
+        let mut xs: TVec<TValue> =
+            tvec!(Tensor::from_shape(&[1, self.context_length], &ids)?.into());
+        if self.model.model().inputs.len() > 1 {
+            xs.push(Tensor::from_shape(&[1, self.context_length], &mask)?.into());
+        }
+        let ys = self.model.run(xs)?;
+        l2_normalized(&ys[0])
+    }
+}
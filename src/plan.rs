@@ -0,0 +1,290 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    archive, build_pb, canonical, dst_of, free_folder, is_json, print_json, src2dst, Error,
+    Journal, Link, Transfer, LOGGER,
+};
+
+/// Where and how to save the results, or to plan the saving.
+#[derive(clap::Args, Debug, Clone)]
+pub struct SaveArgs {
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[arg(short, long)]
+    pub mv: bool,
+
+    /// Save as links instead of copies, falling back to copying when linking fails (e.g. across filesystems)
+    #[arg(long, value_enum, conflicts_with = "mv")]
+    pub link: Option<Link>,
+
+    /// Analyze only, then save the plan of the results to this file (`-` for stdout) for `ilytix apply`
+    #[arg(long, value_name = "PLAN")]
+    pub dry_run: Option<PathBuf>,
+
+    /// Mirror the folders of the input (the indexed collection for `index query`) under each output folder,
+    /// instead of saving by file names only
+    #[arg(long)]
+    pub preserve_tree: bool,
+}
+
+impl SaveArgs {
+    pub fn plan(&self) -> Plan {
+        Plan::new(Transfer::new(self.mv, self.link), self.dry_run.clone())
+    }
+
+    /// Whether the results are only printed, with `--format json` and neither `-o` nor `--dry-run`.
+    pub fn report_only(&self) -> bool {
+        is_json() && self.output.is_none() && self.dry_run.is_none()
+    }
+
+    /// Save the plan of nothing on a dry run, when nothing is to save, returning it for `--dry-run -`.
+    pub fn plan_nothing(&self) -> Result<Option<Plan>> {
+        if self.dry_run.is_none() {
+            return Ok(None);
+        }
+        let mut plan = self.plan();
+        if let Some(output) = &self.output {
+            plan.folder(output)?;
+        }
+        plan.finish()?;
+        Ok(plan.to_stdout().then_some(plan))
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct Args {
+    /// Plan saved by `--dry-run`
+    plan: PathBuf,
+}
+
+/// One file to save.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Action {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    pub action: Transfer,
+    /// Folder of the result, e.g. `Curated`
+    pub reason: String,
+}
+
+impl Action {
+    pub fn run(&self) -> Result<(), Error> {
+        if let Some(parent) = self.dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        src2dst(&self.src, &self.dst, self.action)
+    }
+}
+
+/// Saving of the results, done right away, or planned by `--dry-run` for `ilytix apply`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Plan {
+    /// Output folder, where the journal of the moves goes
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    pub actions: Vec<Action>,
+    #[serde(skip)]
+    transfer: Transfer,
+    /// Where to save the plan, `-` for stdout
+    #[serde(skip)]
+    dry_run: Option<PathBuf>,
+    #[serde(skip)]
+    taken: HashSet<PathBuf>,
+}
+
+impl Plan {
+    pub fn new(transfer: Transfer, dry_run: Option<PathBuf>) -> Self {
+        Self {
+            transfer,
+            dry_run,
+            ..Default::default()
+        }
+    }
+
+    pub fn transfer(&self) -> Transfer {
+        self.transfer
+    }
+
+    /// Output folder, see `make_folders`, only made when not a dry run.
+    pub fn folder<P: AsRef<Path>>(&mut self, p: P) -> Result<PathBuf, Error> {
+        let saveout = std::env::current_dir()?.join(free_folder(p)?);
+        if self.dry_run.is_none() {
+            std::fs::create_dir_all(&saveout)?;
+        }
+        self.output = Some(saveout.clone());
+        Ok(saveout)
+    }
+
+    /// Journal of the moves in the output folder.
+    pub fn journal(&self) -> Option<Journal> {
+        self.output.as_deref().map(Journal::new)
+    }
+
+    /// Save with `action`, moves appended to the journal.
+    fn run(&self, action: &Action) -> Result<(), Error> {
+        action.run()?;
+        if action.action == Transfer::Move {
+            if let Some(journal) = self.journal() {
+                journal.append(action)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Save `src` as `name` in `dir` (see `dst_of`), or plan it on a dry run.
+    /// Members of archives are always extracted, the archives are never modified.
+    pub fn save<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        src: P,
+        name: Q,
+        root: Option<&Path>,
+        dir: &Path,
+        reason: &str,
+    ) -> Result<(), Error> {
+        self.push(src, name, root, dir, reason, self.transfer)
+    }
+
+    /// `save`, but copied instead of moved, for a file saved more than once
+    /// with a later `save` moving it.
+    pub fn save_copy<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        src: P,
+        name: Q,
+        root: Option<&Path>,
+        dir: &Path,
+        reason: &str,
+    ) -> Result<(), Error> {
+        let transfer = match self.transfer {
+            Transfer::Move => Transfer::Copy,
+            x => x,
+        };
+        self.push(src, name, root, dir, reason, transfer)
+    }
+
+    fn push<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        src: P,
+        name: Q,
+        root: Option<&Path>,
+        dir: &Path,
+        reason: &str,
+        transfer: Transfer,
+    ) -> Result<(), Error> {
+        let dst = dst_of(&src, name, root, dir, &self.taken)?;
+        self.taken.insert(dst.clone());
+        let action = Action {
+            src: canonical(&src)?,
+            dst,
+            action: match archive::is_member(&src) {
+                true => Transfer::Copy,
+                false => transfer,
+            },
+            reason: reason.to_string(),
+        };
+        if self.dry_run.is_none() {
+            self.run(&action)?;
+        }
+        self.actions.push(action);
+        Ok(())
+    }
+
+    /// Log where the results went, or save the plan on a dry run.
+    pub fn finish(&self) -> Result<()> {
+        match &self.dry_run {
+            None => {
+                if let Some(saveout) = &self.output {
+                    LOGGER.success("Results saved to", &format!("{}", saveout.display()), "");
+                }
+                self.log_journal();
+            }
+            Some(p) => {
+                LOGGER.success("Planned", &format!("x{}", self.actions.len()), "Dry run");
+                if self.to_stdout() {
+                    // in the document of `--format json` instead
+                    if !is_json() {
+                        print_json(self)?;
+                    }
+                } else {
+                    let f = std::fs::File::create(p)?;
+                    serde_json::to_writer_pretty(std::io::BufWriter::new(f), self)?;
+                    LOGGER.success("Plan saved to", &format!("{}", p.display()), "");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the plan goes to stdout, with `--dry-run -`.
+    pub fn to_stdout(&self) -> bool {
+        self.dry_run
+            .as_deref()
+            .is_some_and(|x| x.as_os_str() == "-")
+    }
+
+    fn log_journal(&self) {
+        if let Some(journal) = self.journal().filter(|x| x.path.exists()) {
+            let path = format!("{}", journal.path.display());
+            LOGGER.success("Journal saved to", &path, "`ilytix undo` to restore");
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(p: P) -> Result<Self> {
+        let f = std::fs::File::open(p)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(f))?)
+    }
+}
+
+impl Args {
+    pub fn run(&self) -> Result<()> {
+        let plan = Plan::load(&self.plan).with_context(|| format!("{}", self.plan.display()))?;
+        LOGGER.success("Plan", &format!("{}", self.plan.display()), "");
+        let _archives = archive::open_all(&plan.actions.iter().map(|x| &x.src).collect::<Vec<_>>());
+        let pb = build_pb(plan.actions.len() as u64, "Applying");
+        let mut cnt_applied = 0;
+        let mut v_conflicts: Vec<(&Action, String)> = Vec::new();
+        for x in plan.actions.iter() {
+            pb.inc(1);
+            let exists = match archive::is_member(&x.src) {
+                true => archive::contains(&x.src),
+                false => x.src.exists(),
+            };
+            if !exists {
+                v_conflicts.push((x, "Source not exist".to_string()));
+            } else if x.dst.symlink_metadata().is_ok() {
+                v_conflicts.push((x, "Destination exists".to_string()));
+            } else {
+                match plan.run(x) {
+                    Ok(()) => cnt_applied += 1,
+                    Err(e) => v_conflicts.push((x, e.to_string())),
+                }
+            }
+        }
+        pb.finish();
+
+        // summary
+        LOGGER.success("Applied", &format!("x{}", cnt_applied), "");
+        plan.log_journal();
+        if !v_conflicts.is_empty() {
+            LOGGER.warn("Conflicts", &format!("x{}", v_conflicts.len()), "Skipped");
+            for (x, reason) in v_conflicts.iter() {
+                LOGGER.warn("", &format!("{}", x.src.display()), reason);
+            }
+        }
+        if is_json() {
+            print_json(&serde_json::json!({
+                "command": "apply",
+                "plan": self.plan,
+                "applied": cnt_applied,
+                "conflicts": v_conflicts
+                    .iter()
+                    .map(|(x, reason)| serde_json::json!({ "action": x, "reason": reason }))
+                    .collect::<Vec<_>>(),
+            }))?;
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,15 @@
 use anyhow::Result;
-use std::path::PathBuf;
-use usearch::ffi::{IndexOptions, MetricKind, ScalarKind};
+use std::path::{Path, PathBuf};
 
-use crate::{build_pb, hash2decial, load_files, make_folders, src2dst, Method, LOGGER};
+use crate::document::{Collection, Failed, Saved};
+use crate::{
+    build_pb, canonical, decode, is_json, log_source, par_map, print_json, thresh_help, tree_root,
+    walk_all, Error, Extractor, FilterArgs, Method, NnArgs, SaveArgs, TextArgs, TextEmbedder,
+    LOGGER,
+};
 
-#[derive(Debug, Clone, clap::ValueEnum)]
+#[derive(Debug, Clone, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Kind {
     Image,
     Text,
@@ -12,190 +17,343 @@ pub enum Kind {
 
 #[derive(clap::Parser, Debug)]
 pub struct Args {
-    #[arg(short, long)]
-    input: String,
+    /// Image or folder, repeatable for several, `-` to read the paths of the files from stdin
+    #[arg(short, long, required_unless_present = "list", conflicts_with = "list")]
+    input: Vec<String>,
 
+    /// Query image, or query text for `--kind text`
     #[arg(long)]
     query: String,
 
     #[arg(short, long, value_enum, default_value_t = Kind::Image)]
     kind: Kind,
 
-    #[arg(short, long)]
-    output: Option<String>,
+    #[command(flatten)]
+    save: SaveArgs,
 
     #[arg(short, long)]
     recursive: bool,
 
-    #[arg(long)]
-    mv: bool,
+    #[command(flatten)]
+    filter: FilterArgs,
 
     #[arg(long, value_enum, default_value_t = Method::BlockHash)]
     method: Method,
 
-    /// The smaller this parameter is, the lower the tolerance
-    #[arg(long, default_value_t = 3.0f32)]
-    thresh: f32,
+    /// Bits of the perceptual hashes, 256 for blockhash and 64 for the others by default
+    #[arg(long)]
+    bits: Option<usize>,
+
+    #[command(flatten)]
+    nn: NnArgs,
+
+    #[command(flatten)]
+    text: TextArgs,
+
+    #[arg(long, help = thresh_help())]
+    thresh: Option<f32>,
+
+    /// Number of the ranked results for `--kind text`
+    #[arg(long, default_value_t = 10)]
+    topk: usize,
 
     #[arg(short, long)]
     verbose: bool,
+
+    /// Number of threads, 0 for all the cores
+    #[arg(short, long, default_value_t = 0)]
+    jobs: usize,
 }
 
-impl Args {
-    fn build_then_register(&self, paths: &[PathBuf]) -> Result<(usearch::Index, Vec<PathBuf>)> {
-        let options = match self.method {
-            Method::BlockHash => IndexOptions {
-                dimensions: 32,
-                metric: MetricKind::Hamming,
-                quantization: ScalarKind::F16,
-                ..Default::default()
-            },
-            _ => todo!(),
-        };
-        let index = usearch::new_index(&options)?;
-        index.reserve(paths.len())?;
-        let pb = build_pb(paths.len() as u64, "Building");
-        let mut v_deprecated: Vec<PathBuf> = Vec::new();
-        for (idx, path) in paths.iter().enumerate() {
-            pb.inc(1);
-            let img = match image::io::Reader::open(path) {
-                Err(_) => {
-                    v_deprecated.push(path.to_path_buf());
-                    continue;
-                }
-                Ok(reader) => match reader.with_guessed_format() {
-                    Err(_) => {
-                        v_deprecated.push(path.to_path_buf());
-                        continue;
-                    }
-                    Ok(x) => match x.decode() {
-                        Err(_) => {
-                            v_deprecated.push(path.to_path_buf());
-                            continue;
-                        }
-                        Ok(x) => x,
-                    },
-                },
-            };
-            match self.method {
-                Method::BlockHash => {
-                    let hash = blockhash::blockhash256(&img);
-                    let hash = hash2decial(&hash.to_string())?;
-                    index.add(idx as u64, &hash)?;
-                }
-                _ => todo!(),
+/// Extract feats then add them to the index with keys starting from `key0`,
+/// returning the files failed to decode with the errors.
+fn register(
+    index: &usearch::Index,
+    paths: &[PathBuf],
+    key0: u64,
+    extractor: &Extractor,
+    jobs: usize,
+) -> Result<Vec<(PathBuf, Error)>, Error> {
+    index
+        .reserve(index.size() + paths.len())
+        .map_err(Error::index)?;
+    let feats = par_map(paths, jobs, "Building", |p| match decode(p) {
+        Err(e) => Ok(Err(Error::decoding(p, e))),
+        Ok(img) => extractor.extract(&img).map(Ok),
+    })?;
+    let mut v_deprecated: Vec<(PathBuf, Error)> = Vec::new();
+    for (idx, (path, feat)) in paths.iter().zip(feats).enumerate() {
+        match feat? {
+            Err(e) => v_deprecated.push((path.to_path_buf(), e)),
+            Ok(feat) => feat.register(index, key0 + idx as u64)?,
+        }
+    }
+    Ok(v_deprecated)
+}
+
+/// Index of the files with their positions as keys, and the files failed to decode with the errors.
+#[allow(clippy::type_complexity)]
+pub fn build_index(
+    paths: &[PathBuf],
+    extractor: &Extractor,
+    jobs: usize,
+) -> Result<(usearch::Index, Vec<(PathBuf, Error)>), Error> {
+    let index = usearch::Index::new(&extractor.index_options()).map_err(Error::index)?;
+    let v_deprecated = register(&index, paths, 0, extractor, jobs)?;
+    Ok((index, v_deprecated))
+}
+
+/// `build_index` with the index logged.
+pub(crate) fn build_then_register(
+    paths: &[PathBuf],
+    extractor: &Extractor,
+    jobs: usize,
+) -> Result<(usearch::Index, Vec<(PathBuf, Error)>)> {
+    let (index, v_deprecated) = build_index(paths, extractor, jobs)?;
+    log_index(&index);
+    Ok((index, v_deprecated))
+}
+
+pub(crate) fn log_index(index: &usearch::Index) {
+    LOGGER.success("Index", "", "");
+    LOGGER.success("", "Capacity", &format!("{}", index.capacity()));
+    LOGGER.success("", "Size", &format!("{}", index.size()));
+    LOGGER.success("", "Dimensions", &format!("{}", index.dimensions()));
+}
+
+/// Text encoder of `--kind text`, `None` for `--kind image`.
+/// Loaded before indexing, so that the wrong options fail early.
+pub fn text_embedder(
+    kind: &Kind,
+    extractor: &Extractor,
+    text: &TextArgs,
+) -> Result<Option<TextEmbedder>, Error> {
+    match kind {
+        Kind::Image => Ok(None),
+        Kind::Text => {
+            if let Extractor::Hash(..) = extractor {
+                return Err(Error::Options(
+                    "Text-image retrival works with `--method nn` only".into(),
+                ));
             }
+            TextEmbedder::new(text).map(Some)
         }
-        pb.finish();
+    }
+}
 
-        // index
-        LOGGER.success("Index", "", "");
-        LOGGER.success("", "Capacity", &format!("{}", index.capacity()));
-        LOGGER.success("", "Size", &format!("{}", index.size()));
-        LOGGER.success("", "Dimensions", &format!("{}", index.dimensions()));
-        if index.size() <= 1 {
-            LOGGER.exit(
-                "Error",
-                "Too few images to deduplicate",
-                &format!("{}", index.size()),
+/// Keys of the indexed images matching the query, with distances in ascending order.
+/// The query is a text with an `embedder` (see `text_embedder`), an image otherwise.
+pub fn retrive(
+    index: &usearch::Index,
+    extractor: &Extractor,
+    embedder: Option<&TextEmbedder>,
+    query: &str,
+    thresh: Option<f32>,
+    topk: usize,
+) -> Result<Vec<(u64, f32)>, Error> {
+    Ok(match embedder {
+        Some(embedder) => {
+            let feat = embedder.embed(query)?;
+            if feat.len() != index.dimensions() {
+                return Err(Error::model(
+                    embedder.path(),
+                    format!(
+                        "The text encoder outputs {} dimensions while the image index has {}",
+                        feat.len(),
+                        index.dimensions()
+                    ),
+                ));
+            }
+
+            // ranked, then filtered if `--thresh` is given
+            let matches = index.search(&feat, topk).map_err(Error::index)?;
+            matches
+                .keys
+                .into_iter()
+                .zip(matches.distances)
+                .filter(|(_, d)| *d <= thresh.unwrap_or(f32::INFINITY))
+                .collect()
+        }
+        None => {
+            let img = decode(query).map_err(|e| Error::decoding(Path::new(query), e))?;
+            let thresh = thresh.unwrap_or(extractor.thresh());
+            extractor.extract(&img)?.search(index, thresh)?
+        }
+    })
+}
+
+#[derive(serde::Serialize, Debug)]
+struct Matched {
+    path: PathBuf,
+    distance: f32,
+    /// Input the file came from, for more than one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    root: Option<PathBuf>,
+}
+
+/// Retrived images, for `--format json`.
+#[derive(serde::Serialize, Debug)]
+struct Document<'a> {
+    command: &'static str,
+    query: &'a str,
+    kind: &'a Kind,
+    #[serde(flatten)]
+    collection: Collection<'a>,
+    count: usize,
+    matched: Vec<Matched>,
+    #[serde(flatten)]
+    saved: Saved,
+}
+
+/// Images retrived for a query, to save with `save_matched`.
+pub(crate) struct Retrived<'a> {
+    pub query: &'a str,
+    pub kind: &'a Kind,
+    pub collection: Collection<'a>,
+    /// Paths with their distances, in ascending order
+    pub matched: Vec<(PathBuf, f32)>,
+    /// Folder the files are relative to, mirrored by `--preserve-tree`
+    pub root: &'a Path,
+}
+
+pub(crate) fn save_matched(x: Retrived, save: &SaveArgs, verbose: bool) -> Result<()> {
+    let v_matched = &x.matched;
+    let walk = x.collection.walk;
+    let mut doc = Document {
+        command: "retrive",
+        query: x.query,
+        kind: x.kind,
+        collection: x.collection,
+        count: v_matched.len(),
+        matched: v_matched
+            .iter()
+            .map(|(p, d)| {
+                let path = canonical(p)?;
+                Ok(Matched {
+                    root: walk.root_of(&path),
+                    path,
+                    distance: *d,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        saved: Saved::default(),
+    };
+
+    // summary
+    LOGGER.success("Matched", &format!("x{}", v_matched.len()), "");
+    if v_matched.is_empty() {
+        if is_json() {
+            return print_json(&doc);
+        }
+        return Err(Error::NoMatch.into());
+    } else if verbose {
+        for (path, distance) in v_matched.iter() {
+            LOGGER.success(
+                "",
+                canonical(path)?.to_str().unwrap(),
+                &format!("{:.4}", distance),
             );
         }
+    }
+    match &save.output {
+        None if save.report_only() => {}
+        None => return Err(Error::NoOutput.into()),
+        Some(output) => {
+            let mut plan = save.plan();
+            let pb = build_pb(
+                v_matched.len() as u64,
+                &format!("Saving[{}]", plan.transfer().name()),
+            );
+            // make dir
+            let saveout = plan.folder(output)?;
+            let root = match save.preserve_tree {
+                true => Some(tree_root(x.root)?),
+                false => None,
+            };
+            for (path, _) in v_matched {
+                pb.inc(1);
+                let filename = path.file_name().unwrap();
+                plan.save(path, filename, root.as_deref(), &saveout, "Matched")?;
+            }
 
-        Ok((index, v_deprecated))
+            pb.finish();
+
+            // summary
+            doc.saved.output = Some(saveout.to_path_buf());
+            plan.finish()?;
+            doc.saved.plan = plan.to_stdout().then_some(plan);
+        }
+    }
+    if is_json() {
+        print_json(&doc)?;
     }
 
+    Ok(())
+}
+
+impl Args {
     pub fn run(&self) -> Result<()> {
         // load all files & build index & extract feats
-        let paths = load_files(&self.input, self.recursive, false, Some("Collection"))?;
-        let (index, _) = self.build_then_register(&paths)?;
-
-        let mut v_matched: Vec<usize> = Vec::new();
-        match self.kind {
-            Kind::Text => todo!(),
-            Kind::Image => {
-                let img = match image::io::Reader::open(&self.query) {
-                    Err(e) => {
-                        anyhow::bail!("{:?} => {:?}", e, self.query);
-                    }
-                    Ok(reader) => match reader.with_guessed_format() {
-                        Err(e) => {
-                            anyhow::bail!("{:?} => {:?}", e, self.query);
-                        }
-                        Ok(x) => match x.decode() {
-                            Err(e) => {
-                                anyhow::bail!("{:?} => {:?}", e, self.query);
-                            }
-                            Ok(x) => x,
-                        },
-                    },
-                };
-                LOGGER.success("Query", &self.query, "");
-
-                match self.method {
-                    Method::BlockHash => {
-                        let hash = blockhash::blockhash256(&img);
-                        let hash = hash2decial(&hash.to_string())?;
-                        let matches = index.search(&hash, index.size())?;
-                        for (k, score) in
-                            matches.keys.into_iter().zip(matches.distances.into_iter())
-                        {
-                            if score <= self.thresh {
-                                v_matched.push(k as usize);
-                            }
-                        }
-                    }
-                    _ => todo!(),
-                }
-            }
-        }
-
-        // summary
-        LOGGER.success("Matched", &format!("x{}", v_matched.len()), "");
-        if v_matched.is_empty() {
-            LOGGER.exit("No image retrived", "--thresh", &format!("{}", self.thresh));
-        } else if self.verbose {
-            for &i in v_matched.iter() {
-                LOGGER.success("", paths[i].canonicalize()?.to_str().unwrap(), "");
-            }
+        let walked = walk_all(&self.input, self.recursive, false, &self.filter)?;
+        log_source("Collection", self.recursive, &self.filter, &walked)?;
+        let paths = &walked.files;
+        let extractor = Extractor::new(self.method, self.bits, Some(&self.nn))?;
+        let embedder = text_embedder(&self.kind, &extractor, &self.text)?;
+        let (index, v_deprecated) = build_then_register(paths, &extractor, self.jobs)?;
+        if index.size() <= 1 {
+            anyhow::bail!("Too few images to retrive from: {}", index.size());
         }
-        match &self.output {
-            None => LOGGER.exit(
-                "Results",
-                "Not Saving",
-                "Use `-o <PATH>` to set the save location",
+        LOGGER.success("Query", &self.query, "");
+        let v_matched = retrive(
+            &index,
+            &extractor,
+            embedder.as_ref(),
+            &self.query,
+            self.thresh,
+            self.topk,
+        )?
+        .into_iter()
+        .map(|(k, d)| (paths[k as usize].to_path_buf(), d))
+        .collect::<Vec<_>>();
+        let retrived = Retrived {
+            query: &self.query,
+            kind: &self.kind,
+            collection: Collection::new(
+                &walked,
+                self.recursive,
+                v_deprecated
+                    .iter()
+                    .map(|(p, e)| Failed::new(p, Some(e)))
+                    .collect(),
             ),
-            Some(output) => {
-                let pb = build_pb(
-                    v_matched.len() as u64,
-                    if !self.mv {
-                        "Saving[Copy]"
-                    } else {
-                        "Saving[Move]"
-                    },
-                );
-                // make dir
-                let mut saveout = make_folders(output)?;
-                std::fs::create_dir_all(&saveout)?;
-                for idx in v_matched {
-                    pb.inc(1);
-                    let path = &paths[idx];
-                    let filename = path.file_name().unwrap().to_str().unwrap();
-                    saveout.push(filename);
-                    src2dst(path, &saveout, self.mv)?;
-                    saveout.pop();
-                }
-
-                pb.finish();
-
-                // summary
-                LOGGER.success(
-                    "Results saved to",
-                    &format!("{}", saveout.canonicalize()?.display()),
-                    "",
-                );
-            }
-        }
+            matched: v_matched,
+            root: &walked.root,
+        };
+        save_matched(retrived, &self.save, self.verbose)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
 
+    #[test]
+    fn text_query_rejected_before_indexing() -> Result<()> {
+        // no image to index, the options are checked first
+        let tmp = tempfile::tempdir()?;
+        let input = tmp.path().join("input");
+        std::fs::create_dir(&input)?;
+        let i = input.to_str().unwrap();
+        let argv = ["retrive", "-i", i, "--kind", "text", "--query", "a cat"];
+        match Args::parse_from(argv)
+            .run()
+            .unwrap_err()
+            .downcast::<Error>()?
+        {
+            Error::Options(x) => assert!(x.contains("--method nn"), "{}", x),
+            e => panic!("{}", e),
+        }
         Ok(())
     }
 }
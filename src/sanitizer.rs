@@ -1,155 +1,333 @@
 use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
+use crate::document::{Collection, Failed, Saved};
 use crate::{
-    build_pb, load_files, make_folders, src2dst, ImageFiles, LOGGER, SAVEOUT_DEPRECATED,
-    SAVEOUT_FILTERED, SAVEOUT_INCORRECT, SAVEOUT_RECTIFIED, SAVEOUT_VALID,
+    build_pb, is_json, log_source, print_json, walk_all, Error, FilterArgs, ImageFiles, SaveArgs,
+    Walked, LOGGER, SAVEOUT_DEPRECATED, SAVEOUT_FILTERED, SAVEOUT_INCORRECT, SAVEOUT_RECTIFIED,
+    SAVEOUT_VALID,
 };
 
 #[derive(clap::Parser, Debug)]
 pub struct Args {
-    #[arg(short, long)]
-    input: String,
+    /// Image or folder, repeatable for several, `-` to read the paths of the files from stdin
+    #[arg(short, long, required_unless_present = "list", conflicts_with = "list")]
+    input: Vec<String>,
 
-    #[arg(short, long)]
-    output: Option<String>,
+    #[command(flatten)]
+    save: SaveArgs,
 
     #[arg(short, long)]
     recursive: bool,
 
-    #[arg(short, long)]
-    mv: bool,
+    #[command(flatten)]
+    filter: FilterArgs,
 
     #[arg(long, default_value_t = 0)]
     min_width: u32,
 
     #[arg(long, default_value_t = 0)]
     min_height: u32,
+
+    /// Number of threads, 0 for all the cores
+    #[arg(short, long, default_value_t = 0)]
+    jobs: usize,
+}
+
+/// Classification of one file, for `--format json`.
+#[derive(Serialize, Debug)]
+struct Checked {
+    path: PathBuf,
+    status: &'static str,
+    width: Option<u32>,
+    height: Option<u32>,
+    /// File name with the suffix of the guessed format
+    rectified: Option<String>,
+    /// Category of the error, for the deprecated
+    category: Option<&'static str>,
+    error: Option<String>,
+    /// Input the file came from, for more than one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    root: Option<PathBuf>,
+}
+
+#[derive(Serialize, Debug)]
+struct Document<'a> {
+    command: &'static str,
+    #[serde(flatten)]
+    collection: Collection<'a>,
+    counts: BTreeMap<&'static str, usize>,
+    /// Deprecated files of each category
+    categories: BTreeMap<&'static str, usize>,
+    files: Vec<Checked>,
+    #[serde(flatten)]
+    saved: Saved,
+}
+
+impl<'a> Document<'a> {
+    fn new(args: &Args, files: &ImageFiles, walk: &'a Walked) -> Result<Self> {
+        let mut xs: Vec<Checked> = Vec::new();
+        let checked = |path: &PathBuf, status, wh: Option<(u32, u32)>, rectified, error| Checked {
+            path: path.to_path_buf(),
+            status,
+            width: wh.map(|x| x.0),
+            height: wh.map(|x| x.1),
+            rectified,
+            category: None,
+            error,
+            root: walk.root_of(path),
+        };
+        for (p, w, h) in files.v_valid.iter() {
+            xs.push(checked(p, "intact", Some((*w, *h)), None, None));
+        }
+        for (p, w, h) in files.v_valid_filtered.iter() {
+            xs.push(checked(p, "filtered", Some((*w, *h)), None, None));
+        }
+        for (p, (name, w, h)) in files.map_incorrect_suffix.iter() {
+            xs.push(checked(
+                p,
+                "incorrect",
+                Some((*w, *h)),
+                Some(name.clone()),
+                None,
+            ));
+        }
+        for (p, (name, w, h)) in files.map_incorrect_suffix_filtered.iter() {
+            let x = checked(
+                p,
+                "incorrect_filtered",
+                Some((*w, *h)),
+                Some(name.clone()),
+                None,
+            );
+            xs.push(x);
+        }
+        for (p, e) in files.map_deprecated.iter() {
+            let mut x = checked(p, "deprecated", None, None, Some(e.to_string()));
+            x.category = Some(e.category());
+            xs.push(x);
+        }
+        xs.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut counts: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for x in xs.iter() {
+            *counts.entry(x.status).or_default() += 1;
+        }
+        let deprecated = files
+            .map_deprecated
+            .iter()
+            .map(|(p, e)| Failed::new(p, Some(e)))
+            .collect();
+        Ok(Self {
+            command: "check",
+            collection: Collection::new(walk, args.recursive, deprecated),
+            counts,
+            categories: files.deprecated_categories(),
+            files: xs,
+            saved: Saved::default(),
+        })
+    }
+}
+
+fn summary(files: &ImageFiles, min_height: u32, min_width: u32) {
+    let filtering = min_height != 0 && min_width != 0;
+    LOGGER.success("Found", &format!("x{}", files.ntotal()), "");
+    LOGGER.success("", SAVEOUT_VALID, &format!("x{}", files.v_valid.len()));
+    if filtering {
+        LOGGER.success(
+            "",
+            &format!("{} ({})", SAVEOUT_VALID, SAVEOUT_FILTERED),
+            &format!("x{}", files.v_valid_filtered.len()),
+        );
+    }
+    LOGGER.success(
+        "",
+        SAVEOUT_INCORRECT,
+        &format!("x{}", files.map_incorrect_suffix.len()),
+    );
+    if filtering {
+        LOGGER.success(
+            "",
+            &format!("{} ({})", SAVEOUT_INCORRECT, SAVEOUT_FILTERED),
+            &format!("x{}", files.map_incorrect_suffix_filtered.len()),
+        );
+    }
+    LOGGER.success(
+        "",
+        SAVEOUT_DEPRECATED,
+        &format!("x{}", files.map_deprecated.len()),
+    );
+    for (category, n) in files.deprecated_categories() {
+        LOGGER.success("", &format!("  {}", category), &format!("x{}", n));
+    }
 }
 
 impl Args {
     pub fn run(&self) -> Result<()> {
-        let paths = load_files(&self.input, self.recursive, false, None)?;
-        let files = ImageFiles::new(&paths, self.min_width, self.min_height)?;
+        let mut walked = walk_all(&self.input, self.recursive, false, &self.filter)?;
+        log_source("Source", self.recursive, &self.filter, &walked)?;
+        let paths = std::mem::take(&mut walked.files);
+        if self.min_height != 0 && self.min_width != 0 {
+            LOGGER.success("Condition filtering", "", "");
+            LOGGER.success("", "Min width", &format!("{}", self.min_width));
+            LOGGER.success("", "Min height", &format!("{}", self.min_height));
+        }
+        let files = ImageFiles::new(&paths, self.min_width, self.min_height, self.jobs)?;
+        summary(&files, self.min_width, self.min_height);
+        let root = walked.root.to_path_buf();
+        let mut doc = Document::new(self, &files, &walked)?;
         if files.is_ok() {
+            doc.saved.plan = self.save.plan_nothing()?;
+            if is_json() {
+                return print_json(&doc);
+            }
             println!("\n🎉 All the images appear to be intact and accurate.");
             return Ok(());
         }
 
         // save
-        match &self.output {
-            None => LOGGER.exit(
-                "Results",
-                "Not Saving",
-                "Use `-o <PATH>` to set the save location",
-            ),
+        match &self.save.output {
+            None if self.save.report_only() => {}
+            None => return Err(Error::NoOutput.into()),
             Some(output) => {
+                let mut plan = self.save.plan();
                 let pb = build_pb(
                     files.ntotal() as u64,
-                    if !self.mv {
-                        "Saving[Copy]"
-                    } else {
-                        "Saving[Move]"
-                    },
+                    &format!("Saving[{}]", plan.transfer().name()),
                 );
-                let saveout = make_folders(output)?;
+                let saveout = plan.folder(output)?;
+                let root = match self.save.preserve_tree {
+                    true => Some(root.to_path_buf()),
+                    false => None,
+                };
+                let root = root.as_deref();
 
                 // deal with valid
-                if !files.v_valid.is_empty() {
-                    let mut saveout = saveout.clone();
-                    saveout.push(SAVEOUT_VALID);
-                    std::fs::create_dir_all(&saveout)?;
-                    for (f, _w, _h) in files.v_valid.iter() {
-                        pb.inc(1);
-                        saveout.push(f.file_name().unwrap());
-                        src2dst(f, &saveout, self.mv)?;
-                        saveout.pop();
-                    }
+                for (f, _w, _h) in files.v_valid.iter() {
+                    pb.inc(1);
+                    let dir = saveout.join(SAVEOUT_VALID);
+                    plan.save(f, f.file_name().unwrap(), root, &dir, SAVEOUT_VALID)?;
                 }
 
                 // deal with valid_filtered
-                if !files.v_valid_filtered.is_empty() {
-                    let mut saveout = saveout.clone();
-                    saveout.push(SAVEOUT_FILTERED);
-                    std::fs::create_dir_all(&saveout)?;
-                    for (f, _w, _h) in files.v_valid_filtered.iter() {
-                        pb.inc(1);
-                        saveout.push(f.file_name().unwrap());
-                        src2dst(f, &saveout, self.mv)?;
-                        saveout.pop();
-                    }
+                for (f, _w, _h) in files.v_valid_filtered.iter() {
+                    pb.inc(1);
+                    let dir = saveout.join(SAVEOUT_FILTERED);
+                    plan.save(f, f.file_name().unwrap(), root, &dir, SAVEOUT_FILTERED)?;
                 }
 
-                // deal with deprecated
-                if !files.map_deprecated_imerr.is_empty() && files.map_deprecated_ioerr.is_empty() {
-                    let mut saveout = saveout.clone();
-                    saveout.push(SAVEOUT_DEPRECATED);
-                    std::fs::create_dir_all(&saveout)?;
-                    for f in files
-                        .map_deprecated_imerr
-                        .keys()
-                        .chain(files.map_deprecated_ioerr.keys())
-                    {
-                        pb.inc(1);
-                        saveout.push(f.file_name().unwrap());
-                        src2dst(f, &saveout, self.mv)?;
-                        saveout.pop();
-                    }
+                // deal with deprecated, one folder for each category
+                for (f, e) in files.map_deprecated.iter() {
+                    pb.inc(1);
+                    let dir = saveout.join(SAVEOUT_DEPRECATED).join(e.category());
+                    plan.save(f, f.file_name().unwrap(), root, &dir, e.category())?;
                 }
 
                 // deal with incorrect
-                if !files.map_incorrect_suffix.is_empty() {
-                    let mut saveout_incorrect = saveout.clone();
-                    let mut saveout_rectified = saveout.clone();
-                    saveout_incorrect.push(SAVEOUT_INCORRECT);
-                    saveout_rectified.push(SAVEOUT_RECTIFIED);
-                    std::fs::create_dir_all(&saveout_incorrect)?;
-                    std::fs::create_dir_all(&saveout_rectified)?;
-                    for (f, (filename, _w, _h)) in files.map_incorrect_suffix.iter() {
-                        pb.inc(1);
-                        // save incorrect
-                        saveout_incorrect.push(f.file_name().unwrap());
-                        src2dst(f, &saveout_incorrect, self.mv)?;
-                        saveout_incorrect.pop();
-
-                        // save rectified
-                        saveout_rectified.push(filename);
-                        src2dst(f, &saveout_rectified, self.mv)?;
-                        saveout_rectified.pop();
-                    }
+                for (f, (filename, _w, _h)) in files.map_incorrect_suffix.iter() {
+                    pb.inc(1);
+                    // save incorrect, copied since saved again below
+                    let dir = saveout.join(SAVEOUT_INCORRECT);
+                    plan.save_copy(f, f.file_name().unwrap(), root, &dir, SAVEOUT_INCORRECT)?;
+
+                    // save rectified
+                    let dir = saveout.join(SAVEOUT_RECTIFIED);
+                    plan.save(f, filename, root, &dir, SAVEOUT_RECTIFIED)?;
                 }
 
                 // deal with incorrect_filtered
-                if !files.map_incorrect_suffix_filtered.is_empty() {
-                    let mut saveout_incorrect = saveout.clone();
-                    let mut saveout_filtered = saveout.clone();
-                    saveout_incorrect.push(SAVEOUT_INCORRECT);
-                    saveout_filtered.push(SAVEOUT_FILTERED);
-                    std::fs::create_dir_all(&saveout_incorrect)?;
-                    std::fs::create_dir_all(&saveout_filtered)?;
-                    for (f, (filename, _w, _h)) in files.map_incorrect_suffix_filtered.iter() {
-                        pb.inc(1);
-                        // save incorrect
-                        saveout_incorrect.push(f.file_name().unwrap());
-                        src2dst(f, &saveout_incorrect, self.mv)?;
-                        saveout_incorrect.pop();
-
-                        // rectify then save to filtered
-                        saveout_filtered.push(filename);
-                        src2dst(f, &saveout_filtered, self.mv)?;
-                        saveout_filtered.pop();
-                    }
+                for (f, (filename, _w, _h)) in files.map_incorrect_suffix_filtered.iter() {
+                    pb.inc(1);
+                    // save incorrect, copied since saved again below
+                    let dir = saveout.join(SAVEOUT_INCORRECT);
+                    plan.save_copy(f, f.file_name().unwrap(), root, &dir, SAVEOUT_INCORRECT)?;
+
+                    // rectify then save to filtered
+                    let dir = saveout.join(SAVEOUT_FILTERED);
+                    plan.save(f, filename, root, &dir, SAVEOUT_FILTERED)?;
                 }
                 pb.finish();
 
                 // summary
-                LOGGER.success(
-                    "Results saved to",
-                    &format!("{}", saveout.canonicalize()?.display()),
-                    "",
-                );
+                doc.saved.output = Some(saveout.to_path_buf());
+                plan.finish()?;
+                doc.saved.plan = plan.to_stdout().then_some(plan);
             }
         }
+        if is_json() {
+            print_json(&doc)?;
+        }
+
+        Ok(())
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn move_incorrect_suffix() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let input = tmp.path().join("input");
+        std::fs::create_dir(&input)?;
+        let misnamed = |name: &str| -> Result<()> {
+            image::RgbImage::new(8, 8).save(input.join("x.bmp"))?;
+            std::fs::rename(input.join("x.bmp"), input.join(name))?;
+            Ok(())
+        };
+        let check = |out: &std::path::Path, plan: Option<&std::path::Path>| -> Result<()> {
+            let (i, o) = (input.to_str().unwrap(), out.to_str().unwrap());
+            let mut argv = vec!["check", "-i", i, "-o", o, "--mv"];
+            if let Some(plan) = plan {
+                argv.extend(["--dry-run", plan.to_str().unwrap()]);
+            }
+            Args::parse_from(argv).run()
+        };
+
+        // copied to Incorrect, then moved to Rectified
+        misnamed("x.jpg")?;
+        let out = tmp.path().join("out");
+        check(&out, None)?;
+        assert!(out.join(SAVEOUT_INCORRECT).join("x.jpg").exists());
+        assert!(out.join(SAVEOUT_RECTIFIED).join("x.bmp").exists());
+        assert!(!input.join("x.jpg").exists());
+
+        // the same, planned then applied
+        misnamed("x.jpg")?;
+        let (out, plan) = (tmp.path().join("planned"), tmp.path().join("plan.json"));
+        check(&out, Some(&plan))?;
+        assert!(input.join("x.jpg").exists() && !out.exists());
+        crate::plan::Args::parse_from(["apply", plan.to_str().unwrap()]).run()?;
+        assert!(out.join(SAVEOUT_INCORRECT).join("x.jpg").exists());
+        assert!(out.join(SAVEOUT_RECTIFIED).join("x.bmp").exists());
+        assert!(!input.join("x.jpg").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn colliding_names_by_path() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let input = tmp.path().join("input");
+        // created in the reverse order of the paths
+        for (sub, size) in [("c", 10), ("b", 9), ("a", 8)] {
+            std::fs::create_dir_all(input.join(sub))?;
+            image::RgbImage::new(size, size).save(input.join(sub).join("x.bmp"))?;
+        }
+        // saved only when some is not intact
+        std::fs::write(input.join("bad.png"), "not an image")?;
+        let out = tmp.path().join("out");
+        let (i, o) = (input.to_str().unwrap(), out.to_str().unwrap());
+        Args::parse_from(["check", "-i", i, "-r", "-o", o]).run()?;
+
+        // suffixed in the order of the paths
+        let valid = out.join(SAVEOUT_VALID);
+        for (sub, name) in [("a", "x.bmp"), ("b", "x-1.bmp"), ("c", "x-2.bmp")] {
+            let src = std::fs::read(input.join(sub).join("x.bmp"))?;
+            assert_eq!(std::fs::read(valid.join(name))?, src, "{}", name);
+        }
         Ok(())
     }
 }
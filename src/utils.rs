@@ -1,65 +1,377 @@
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use walkdir::WalkDir;
 
-use crate::LOGGER;
-
-pub fn hash2decial(s_hash: &str) -> Result<Vec<f32>> {
-    let s_hash = s_hash.chars().collect::<Vec<_>>();
-    let s = s_hash
-        .chunks(2)
-        .map(|c| {
-            let c = c.iter().collect::<String>();
-            u8::from_str_radix(&c, 16).unwrap() as f32
-        })
-        .collect::<Vec<f32>>();
-    Ok(s)
-}
-
-pub fn src2dst(src: &PathBuf, dst: &PathBuf, mv: bool) -> Result<()> {
-    if mv {
-        match std::fs::rename(src, dst) {
-            Ok(_) => {}
-            Err(e) => LOGGER.exit(
-                "Error when moving",
-                &format!("{}", e),
-                &format!("{}", dst.canonicalize()?.display()),
-            ),
+use crate::{archive, Error, FilterArgs, LOGGER};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Print one JSON document on stdout in place of the logs and progress bars.
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Draw the progress bars of the long steps, off by default, so the library prints nothing.
+pub fn set_progress(progress: bool) {
+    PROGRESS.store(progress, Ordering::Relaxed);
+}
+
+pub fn print_json<T: serde::Serialize>(doc: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(doc)?);
+    Ok(())
+}
+
+/// Indexes up to this size are searched exactly (brute force), the larger ones with HNSW.
+pub const EXACT_SEARCH_MAX: usize = 10_000;
+
+/// Help of `--thresh`, for dedup, retrive and index query.
+pub(crate) fn thresh_help() -> String {
+    format!(
+        "The smaller this parameter is, the lower the tolerance. \
+        Hamming distance for hashes (3 for blockhash, bits / 16 for the others by default), \
+        cosine distance for `--method nn` (0.05 by default). \
+        Searched exactly up to {} images, approximately beyond, which may rarely miss a neighbor",
+        EXACT_SEARCH_MAX
+    )
+}
+
+/// Collect all the neighbors within `radius`, growing `k` until the radius is exceeded.
+/// Exact up to `EXACT_SEARCH_MAX` items, approximate beyond, where a neighbor may rarely be missed.
+pub fn search_radius<T: usearch::VectorType>(
+    index: &usearch::Index,
+    query: &[T],
+    radius: f32,
+) -> Result<Vec<(u64, f32)>, Error> {
+    radius_search(index, query, radius, index.size() <= EXACT_SEARCH_MAX)
+}
+
+fn radius_search<T: usearch::VectorType>(
+    index: &usearch::Index,
+    query: &[T],
+    radius: f32,
+    exact: bool,
+) -> Result<Vec<(u64, f32)>, Error> {
+    let mut k = 16;
+    loop {
+        let matches = match exact {
+            true => index.exact_search(query, k),
+            false => index.search(query, k),
         }
-    } else {
-        match std::fs::copy(src, dst) {
-            Ok(_) => {}
-            Err(e) => LOGGER.exit(
-                "Error when copying",
-                &format!("{}", e),
-                &format!("{}", dst.canonicalize()?.display()),
-            ),
+        .map_err(Error::index)?;
+        let n = matches.keys.len();
+        let exceeded = match matches.distances.last() {
+            Some(&d) => d > radius,
+            None => true,
+        };
+        if exceeded || n < k || k >= index.size() {
+            return Ok(matches
+                .keys
+                .into_iter()
+                .zip(matches.distances)
+                .filter(|(_, d)| *d <= radius)
+                .collect());
         }
+        k *= 2;
     }
-    Ok(())
 }
 
-pub fn make_folders<P: AsRef<Path>>(p: P) -> Result<PathBuf> {
+#[derive(Debug, Clone, Copy, clap::ValueEnum, PartialEq)]
+pub enum Link {
+    /// Hard link, sharing the data within a filesystem
+    Hard,
+    /// Symbolic link to the absolute path of the source
+    Sym,
+    /// Copy-on-write clone, on filesystems supporting it (Btrfs, XFS, APFS, ...)
+    Reflink,
+}
+
+/// How the results are saved, from `--mv` and `--link`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Transfer {
+    #[default]
+    Copy,
+    Move,
+    Link(Link),
+}
+
+impl Transfer {
+    pub fn new(mv: bool, link: Option<Link>) -> Self {
+        match (mv, link) {
+            (_, Some(link)) => Self::Link(link),
+            (true, None) => Self::Move,
+            (false, None) => Self::Copy,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Copy => "Copy",
+            Self::Move => "Move",
+            Self::Link(Link::Hard) => "Hardlink",
+            Self::Link(Link::Sym) => "Symlink",
+            Self::Link(Link::Reflink) => "Reflink",
+        }
+    }
+}
+
+impl From<Transfer> for String {
+    fn from(x: Transfer) -> Self {
+        x.name().to_string()
+    }
+}
+
+impl TryFrom<String> for Transfer {
+    type Error = String;
+
+    fn try_from(x: String) -> Result<Self, Self::Error> {
+        [
+            Self::Copy,
+            Self::Move,
+            Self::Link(Link::Hard),
+            Self::Link(Link::Sym),
+            Self::Link(Link::Reflink),
+        ]
+        .into_iter()
+        .find(|t| t.name() == x)
+        .ok_or(format!("Unknown action: {}", x))
+    }
+}
+
+fn symlink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let src = src.canonicalize()?;
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(src, dst);
+    #[cfg(not(windows))]
+    return std::os::unix::fs::symlink(src, dst);
+}
+
+/// Save `src` to `dst`, links fall back to copies when linking fails, e.g. across filesystems.
+/// Members of archives are extracted.
+pub fn src2dst(src: &PathBuf, dst: &PathBuf, transfer: Transfer) -> Result<(), Error> {
+    let copy = || match archive::is_member(src) {
+        true => archive::extract(src, dst),
+        false => std::fs::copy(src, dst).map(|_| ()),
+    };
+    let y = match transfer {
+        Transfer::Copy => copy(),
+        Transfer::Move => std::fs::rename(src, dst),
+        Transfer::Link(Link::Hard) => std::fs::hard_link(src, dst).or_else(|_| copy()),
+        Transfer::Link(Link::Sym) => symlink(src, dst).or_else(|_| copy()),
+        Transfer::Link(Link::Reflink) => reflink_copy::reflink(src, dst).or_else(|_| copy()),
+    };
+    y.map_err(|source| Error::Transfer {
+        src: src.to_path_buf(),
+        dst: dst.to_path_buf(),
+        transfer,
+        source,
+    })
+}
+
+/// Destination of `src` saved as `name` in `dir`, under its folder relative to `root` if given.
+/// A taken destination gets a `-1`, `-2`, ... suffix on the file stem instead of being overwritten.
+/// The `taken` ones are treated as existing, for the planned but not yet saved.
+pub fn dst_of<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    name: Q,
+    root: Option<&Path>,
+    dir: &Path,
+    taken: &HashSet<PathBuf>,
+) -> Result<PathBuf, Error> {
+    let mut dst = dir.to_path_buf();
+    if let Some(root) = root {
+        // a member under a folder named as its archive, e.g. `images.zip/train/0.jpg`
+        let src = canonical(src)?;
+        let src = match archive::split(&src) {
+            Some((archive, name)) => archive.join(name),
+            None => src,
+        };
+        if let Some(parent) = src.parent().and_then(|x| x.strip_prefix(root).ok()) {
+            dst.push(parent);
+        }
+    }
+    let name = name.as_ref();
+    dst.push(name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let mut cnt = 1;
+    while dst.exists() || taken.contains(&dst) {
+        dst.set_file_name(match name.extension() {
+            None => format!("{}-{}", stem, cnt),
+            Some(ext) => format!("{}-{}.{}", stem, cnt, ext.to_string_lossy()),
+        });
+        cnt += 1;
+    }
+    Ok(dst)
+}
+
+/// Root of the tree mirrored by `--preserve-tree`, the input folder or the folder of the input file.
+pub fn tree_root<P: AsRef<Path>>(input: P) -> Result<PathBuf, Error> {
+    let input = input.as_ref().canonicalize()?;
+    Ok(match input.parent() {
+        Some(parent) if input.is_file() => parent.to_path_buf(),
+        _ => input,
+    })
+}
+
+/// `canonicalize` of a file, or of the archive of a member.
+pub fn canonical<P: AsRef<Path>>(p: P) -> Result<PathBuf, Error> {
+    let p = p.as_ref();
+    let y = match archive::split(p) {
+        Some((x, name)) => x.canonicalize().map(|x| archive::member(x, &name)),
+        None => p.canonicalize(),
+    };
+    y.map_err(|source| Error::Unreadable {
+        path: p.to_path_buf(),
+        source,
+    })
+}
+
+/// Format of the image guessed from the head of its content, `None` if unknown or unreadable.
+pub fn guess_format<P: AsRef<Path>>(p: P) -> Option<image::ImageFormat> {
+    let mut buf = Vec::new();
+    match archive::is_member(&p) {
+        true => buf = archive::read(p).ok()?,
+        false => {
+            let f = std::fs::File::open(p).ok()?;
+            std::io::Read::read_to_end(&mut std::io::Read::take(f, 64), &mut buf).ok()?;
+        }
+    }
+    image::guess_format(&buf).ok()
+}
+
+/// Content checksum of a file, xxh3 64 bits.
+pub fn checksum<P: AsRef<Path>>(p: P) -> Result<u64, Error> {
+    let p = p.as_ref();
+    let unreadable = |source| Error::Unreadable {
+        path: p.to_path_buf(),
+        source,
+    };
+    if archive::is_member(p) {
+        return Ok(xxhash_rust::xxh3::xxh3_64(
+            &archive::read(p).map_err(unreadable)?,
+        ));
+    }
+    let mut f = std::fs::File::open(p).map_err(unreadable)?;
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = std::io::Read::read(&mut f, &mut buf).map_err(unreadable)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.digest())
+}
+
+/// File size and modification time in nanoseconds since the unix epoch.
+/// The modification time of a member is the one of its archive.
+pub fn stat<P: AsRef<Path>>(p: P) -> Result<(u64, u64), Error> {
+    let p = p.as_ref();
+    let unreadable = |source| Error::Unreadable {
+        path: p.to_path_buf(),
+        source,
+    };
+    let (size, meta) = match archive::split(p) {
+        Some((x, _)) => (archive::size(p)?, std::fs::metadata(x).map_err(unreadable)?),
+        None => {
+            let meta = std::fs::metadata(p).map_err(unreadable)?;
+            (meta.len(), meta)
+        }
+    };
+    let mtime = meta
+        .modified()
+        .map_err(unreadable)?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| unreadable(std::io::Error::other(e)))?
+        .as_nanos() as u64;
+    Ok((size, mtime))
+}
+
+pub fn decode<P: AsRef<Path>>(p: P) -> Result<image::DynamicImage, image::ImageError> {
+    match archive::is_member(&p) {
+        true => archive::reader(p)?.with_guessed_format()?.decode(),
+        false => image::io::Reader::open(p)?.with_guessed_format()?.decode(),
+    }
+}
+
+/// Content of a file, or of a member.
+pub fn read_all<P: AsRef<Path>>(p: P) -> std::io::Result<Vec<u8>> {
+    match archive::is_member(&p) {
+        true => archive::read(p),
+        false => std::fs::read(p),
+    }
+}
+
+/// `decode` of the content of `p` already read, see `read_all`.
+pub fn decode_from<P: AsRef<Path>>(
+    p: P,
+    buf: &[u8],
+) -> Result<image::DynamicImage, image::ImageError> {
+    let mut reader = image::io::Reader::new(std::io::Cursor::new(buf));
+    if let Ok(format) = image::ImageFormat::from_path(p) {
+        reader.set_format(format);
+    }
+    reader.with_guessed_format()?.decode()
+}
+
+/// Map `f` over `paths` with `jobs` threads (0 for all the cores), keeping the input order.
+pub fn par_map<T, F>(paths: &[PathBuf], jobs: usize, prefix: &str, f: F) -> Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(&PathBuf) -> T + Sync + Send,
+{
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+    let pb = build_pb(paths.len() as u64, prefix);
+    let ys = pool.install(|| {
+        paths
+            .par_iter()
+            .map(|p| {
+                let y = f(p);
+                pb.inc(1);
+                y
+            })
+            .collect()
+    });
+    pb.finish();
+    Ok(ys)
+}
+
+/// Path of `p`, suffixed with `-1`, `-2`, ... if taken.
+pub fn free_folder<P: AsRef<Path>>(p: P) -> Result<PathBuf, Error> {
     let p = p.as_ref();
     let mut saveout = p.to_path_buf();
-    let name = match p.file_name() {
-        None => panic!(
-            "Error: Can not make folders becuase of the incorrect path: {:?}",
-            p
-        ),
-        Some(name) => name.to_str().unwrap(),
+    let name = match p.file_name().and_then(|x| x.to_str()) {
+        None => return Err(Error::InvalidPath(p.to_path_buf())),
+        Some(name) => name,
     };
     let mut cnt = 1;
     while saveout.exists() {
         saveout.set_file_name(format!("{}-{}", name, cnt));
         cnt += 1;
     }
+    Ok(saveout)
+}
+
+pub fn make_folders<P: AsRef<Path>>(p: P) -> Result<PathBuf, Error> {
+    let saveout = free_folder(p)?;
     std::fs::create_dir_all(&saveout)?;
     Ok(saveout)
 }
 
 pub fn build_pb(size: u64, prefix: &str) -> ProgressBar {
+    if !PROGRESS.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
     let pb = ProgressBar::new(size);
     pb.set_style(
             ProgressStyle::with_template(
@@ -72,63 +384,486 @@ pub fn build_pb(size: u64, prefix: &str) -> ProgressBar {
     pb
 }
 
+/// A file reached through symlinks, by `--follow-links`.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Linked {
+    pub link: PathBuf,
+    /// Real path of the file
+    pub path: PathBuf,
+}
+
+/// Files of a walk, with the symlinks followed.
+#[derive(serde::Serialize, Debug, Default)]
+pub struct Walked {
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+    /// The input, canonicalized, or the list of the files, `-` for stdin
+    #[serde(skip)]
+    pub source: PathBuf,
+    /// Folder the files are relative to, the common one of the listed files
+    #[serde(skip)]
+    pub root: PathBuf,
+    /// Whether the files are from a list, or stdin
+    #[serde(skip)]
+    pub listed: bool,
+    /// Listed files not exist, skipped
+    #[serde(skip)]
+    pub missing: Vec<PathBuf>,
+    /// Inputs of `walk_all`, for more than one
+    pub roots: Vec<PathBuf>,
+    /// Input of each file (real path) of `walk_all`, for more than one
+    #[serde(skip)]
+    pub origins: HashMap<PathBuf, PathBuf>,
+    /// Loaded through symlinks
+    pub links: Vec<Linked>,
+    /// Skipped, the real file is loaded through another path
+    pub duplicates: Vec<Linked>,
+    /// Skipped symlinks pointing to their own ancestors
+    pub cycles: Vec<PathBuf>,
+    /// Archives walked, kept open for reading their members
+    #[serde(skip)]
+    pub archives: Vec<archive::Opened>,
+}
+
+impl Walked {
+    pub fn is_plain(&self) -> bool {
+        self.links.is_empty()
+            && self.duplicates.is_empty()
+            && self.cycles.is_empty()
+            && self.roots.is_empty()
+    }
+
+    /// Input the file came from, for more than one.
+    pub fn root_of<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        self.origins.get(path.as_ref()).cloned()
+    }
+}
+
+/// Files of `source`, or the members of an archive, filtered by `filter` unless a single file.
+/// The archive is closed once returned, so reading each member opens and indexes it again,
+/// slow for a `.tar.gz`. Keep the `Walked` of `walk` while reading them instead.
 pub fn load_files<P: AsRef<Path>>(
     source: P,
     recursive: bool,
     hidden_include: bool,
-    prefix: Option<&str>,
-) -> Result<Vec<PathBuf>> {
+    filter: &FilterArgs,
+) -> Result<Vec<PathBuf>, Error> {
+    Ok(walk(source, recursive, hidden_include, filter)?.files)
+}
+
+/// `walk` of each of the inputs, a file found under several of them is loaded once.
+pub fn walk_all<P: AsRef<Path>>(
+    sources: &[P],
+    recursive: bool,
+    hidden_include: bool,
+    filter: &FilterArgs,
+) -> Result<Walked, Error> {
+    match sources {
+        [] => return walk("", recursive, hidden_include, filter),
+        [source] => return walk(source, recursive, hidden_include, filter),
+        _ => {}
+    }
+    let mut walked = Walked::default();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    for (idx, source) in sources.iter().enumerate() {
+        let x = walk(source, recursive, hidden_include, filter)?;
+        walked.root = match idx {
+            0 => x.root,
+            _ => common_folder(&walked.root, &x.root),
+        };
+        for y in x.files.into_iter() {
+            let real = canonical(&y)?;
+            if seen.insert(real.clone()) {
+                walked.origins.insert(real, x.source.to_path_buf());
+                walked.files.push(y);
+            } else {
+                walked.duplicates.push(Linked {
+                    link: y,
+                    path: real,
+                });
+            }
+        }
+        walked.listed |= x.listed;
+        walked.missing.extend(x.missing);
+        walked.links.extend(x.links);
+        walked.duplicates.extend(x.duplicates);
+        walked.cycles.extend(x.cycles);
+        walked.archives.extend(x.archives);
+        walked.roots.push(x.source);
+    }
+    walked.source = walked.root.to_path_buf();
+    Ok(walked)
+}
+
+/// Common ancestor of two folders.
+fn common_folder(a: &Path, b: &Path) -> PathBuf {
+    a.components()
+        .zip(b.components())
+        .take_while(|(a, b)| a == b)
+        .map(|x| x.0)
+        .collect()
+}
+
+/// `load_files` with the symlinks followed by `--follow-links` reported.
+/// The files are ordered by path, so are the results and the `-1` suffixes of the saved ones.
+pub fn walk<P: AsRef<Path>>(
+    source: P,
+    recursive: bool,
+    hidden_include: bool,
+    filter: &FilterArgs,
+) -> Result<Walked, Error> {
     let source = source.as_ref();
-    let prefix = prefix.unwrap_or("Source");
+    if let Some(list) = &filter.list {
+        return walk_list(list, filter);
+    }
+    if source.as_os_str() == "-" {
+        return walk_list(source, filter);
+    }
     if !source.exists() {
-        LOGGER.exit(prefix, " Not Exist", source.to_str().unwrap());
+        return Err(Error::NotExist(source.to_path_buf()));
     }
-    if source.is_symlink() {
-        LOGGER.exit(prefix, " Is Symlink", source.to_str().unwrap());
+    if source.is_symlink() && !filter.follow_links {
+        return Err(Error::Symlink(source.to_path_buf()));
     }
-    let ys = if source.is_file() {
-        ("File", vec![source.to_path_buf()])
+    let mut walked = Walked {
+        source: source.canonicalize()?,
+        root: tree_root(source)?,
+        ..Default::default()
+    };
+    if source.is_file() && archive::is_archive(source) {
+        let x = archive::open(source)?;
+        walked.files = walk_archive(&x, recursive, hidden_include, filter)?;
+        walked.archives.push(x);
+    } else if source.is_file() {
+        walked.files.push(source.to_path_buf());
     } else {
         let mut ys: Vec<PathBuf> = Vec::new();
-        for entry in WalkDir::new(source).into_iter().filter_entry(|x| {
-            let x = x
+        let f = filter.build()?;
+        let max_depth = match recursive {
+            true => filter.max_depth.unwrap_or(usize::MAX),
+            false => 1,
+        };
+        let walker = WalkDir::new(source)
+            .max_depth(max_depth)
+            .follow_links(filter.follow_links)
+            .sort_by_file_name()
+            .into_iter();
+        for entry in walker.filter_entry(|x| {
+            let hidden = x
                 .file_name()
                 .to_str()
                 .map(|s| s.starts_with('.'))
                 .unwrap_or(false);
-            if hidden_include {
-                x
-            } else {
-                !x
-            }
+            // excluded folders are skipped as a whole
+            let excluded = x.depth() > 0
+                && x.file_type().is_dir()
+                && f.excludes(x.path().strip_prefix(source).unwrap_or(x.path()));
+            hidden == hidden_include && !excluded
         }) {
             match entry {
                 Ok(entry) => {
-                    // non-recrusive
-                    if !recursive && entry.depth() > 1 {
-                        continue;
-                    }
-                    // symlink excluded
-                    if entry.path_is_symlink() {
+                    // symlink excluded, unless followed
+                    if entry.path_is_symlink() && !filter.follow_links {
                         continue;
                     }
                     // directory excluded
                     if entry.file_type().is_dir() {
                         continue;
                     }
+                    // filtered
+                    if !f.matches(entry.path().strip_prefix(source).unwrap_or(entry.path())) {
+                        continue;
+                    }
                     ys.push(entry.path().to_path_buf());
                 }
-                Err(_) => {
+                Err(e) => {
+                    if e.loop_ancestor().is_some() {
+                        walked.cycles.extend(e.path().map(|x| x.to_path_buf()));
+                    }
                     continue;
                 }
             }
         }
-        ("Folder", ys)
+        walked.files = match filter.follow_links {
+            true => dedup_links(source, ys, &mut walked)?,
+            false => ys,
+        };
+    }
+    Ok(walked)
+}
+
+/// Members of an archive, filtered like the files of a folder, relative to the archive.
+fn walk_archive(
+    source: &archive::Opened,
+    recursive: bool,
+    hidden_include: bool,
+    filter: &FilterArgs,
+) -> Result<Vec<PathBuf>, Error> {
+    let f = filter.build()?;
+    let max_depth = match recursive {
+        true => filter.max_depth.unwrap_or(usize::MAX),
+        false => 1,
+    };
+    let mut ys: Vec<PathBuf> = Vec::new();
+    for y in source.members().into_iter() {
+        let rel = PathBuf::from(archive::split(&y).map(|x| x.1).unwrap_or_default());
+        let hidden = rel.iter().any(|x| x.to_string_lossy().starts_with('.'));
+        // excluded folders are skipped as a whole
+        let excluded = rel
+            .ancestors()
+            .skip(1)
+            .any(|x| !x.as_os_str().is_empty() && f.excludes(x));
+        if rel.iter().count() > max_depth
+            || hidden && !hidden_include
+            || excluded
+            || !f.matches(&rel)
+        {
+            continue;
+        }
+        ys.push(y);
+    }
+    ys.sort();
+    Ok(ys)
+}
+
+/// Paths of a list, `-` for stdin, relative to the folder of the list, or the current one for stdin.
+fn read_list(list: &Path) -> Result<Vec<PathBuf>, Error> {
+    let (s, dir) = match list.as_os_str() == "-" {
+        true => (std::io::read_to_string(std::io::stdin())?, PathBuf::new()),
+        false => (
+            std::fs::read_to_string(list).map_err(|source| Error::Unreadable {
+                path: list.to_path_buf(),
+                source,
+            })?,
+            list.parent().map(|x| x.to_path_buf()).unwrap_or_default(),
+        ),
     };
-    let source = source.canonicalize()?;
-    LOGGER.success(prefix, source.to_str().unwrap(), ys.0);
-    LOGGER.success("Recursively", &format!("{:?}", &recursive), "");
-    Ok(ys.1)
+    let mut ys: Vec<PathBuf> = Vec::new();
+    for (idx, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |reason: String| Error::List {
+            path: list.to_path_buf(),
+            line: idx + 1,
+            reason,
+        };
+        // JSONL with a `path` field
+        let y = match line.starts_with('{') {
+            true => {
+                let x: serde_json::Value =
+                    serde_json::from_str(line).map_err(|e| invalid(e.to_string()))?;
+                match x.get("path").and_then(|x| x.as_str()) {
+                    Some(x) => PathBuf::from(x),
+                    None => return Err(invalid("No `path` field".to_string())),
+                }
+            }
+            false => PathBuf::from(line),
+        };
+        ys.push(dir.join(y));
+    }
+    Ok(ys)
+}
+
+/// `walk` of the files of a list, the folders and the symlinks (unless followed) are skipped.
+fn walk_list(list: &Path, filter: &FilterArgs) -> Result<Walked, Error> {
+    let f = filter.build()?;
+    let mut walked = Walked {
+        source: match list.as_os_str() == "-" {
+            true => list.to_path_buf(),
+            false => list.canonicalize()?,
+        },
+        listed: true,
+        ..Default::default()
+    };
+
+    // the listed files, with their real paths
+    let mut xs: Vec<(PathBuf, PathBuf, bool)> = Vec::new();
+    for y in read_list(list)?.into_iter() {
+        let metadata = match y.symlink_metadata() {
+            Ok(x) => x,
+            Err(_) => {
+                walked.missing.push(y);
+                continue;
+            }
+        };
+        if metadata.is_symlink() && !filter.follow_links || !y.is_file() {
+            continue;
+        }
+        match y.canonicalize() {
+            Ok(real) => xs.push((y, real, metadata.is_symlink())),
+            Err(_) => walked.missing.push(y),
+        }
+    }
+
+    // filtered relative to the common folder of the listed files
+    let listed = xs
+        .iter()
+        .map(|(_, real, _)| real.parent().unwrap_or(Path::new("/")).to_path_buf())
+        .reduce(|a, b| common_folder(&a, &b))
+        .unwrap_or_default();
+    let mut ys: Vec<PathBuf> = Vec::new();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    for (y, real, is_symlink) in xs.into_iter() {
+        // excluded folders are skipped as a whole
+        let rel = real.strip_prefix(&listed).unwrap_or(&real);
+        let excluded = rel
+            .ancestors()
+            .skip(1)
+            .any(|x| !x.as_os_str().is_empty() && f.excludes(x));
+        if excluded || !f.matches(rel) {
+            continue;
+        }
+        if !seen.insert(real.clone()) {
+            if is_symlink {
+                walked.duplicates.push(Linked {
+                    link: y,
+                    path: real,
+                });
+            }
+            continue;
+        }
+        if is_symlink {
+            walked.links.push(Linked {
+                link: y.to_path_buf(),
+                path: real.clone(),
+            });
+        }
+        // the common folder
+        let parent = real.parent().unwrap_or(Path::new("/"));
+        walked.root = match ys.is_empty() {
+            true => parent.to_path_buf(),
+            false => common_folder(&walked.root, parent),
+        };
+        ys.push(y);
+    }
+    walked.files = ys;
+    Ok(walked)
+}
+
+/// Keep one path of each real file, the one without symlinks preferred, then the shallowest.
+fn dedup_links(
+    source: &Path,
+    ys: Vec<PathBuf>,
+    walked: &mut Walked,
+) -> Result<Vec<PathBuf>, Error> {
+    let root = source.canonicalize()?;
+    let mut xs: Vec<(PathBuf, PathBuf, bool)> = Vec::new();
+    for y in ys.into_iter() {
+        let real = match y.canonicalize() {
+            Ok(real) => real,
+            Err(_) => continue, // dangling
+        };
+        let linked = y.strip_prefix(source).map(|x| root.join(x)).ok() != Some(real.clone());
+        xs.push((y, real, linked));
+    }
+    let mut seen: HashSet<PathBuf> = xs
+        .iter()
+        .filter(|x| !x.2)
+        .map(|x| x.1.to_path_buf())
+        .collect();
+    let mut v_linked: Vec<&(PathBuf, PathBuf, bool)> = xs.iter().filter(|x| x.2).collect();
+    v_linked.sort_by_key(|x| (x.0.components().count(), x.0.to_path_buf()));
+    let mut kept: HashSet<PathBuf> = HashSet::new();
+    for (link, path, _) in v_linked.into_iter() {
+        if seen.insert(path.to_path_buf()) {
+            kept.insert(link.to_path_buf());
+        }
+    }
+    let mut files: Vec<PathBuf> = Vec::new();
+    for (link, path, linked) in xs.into_iter() {
+        if !linked {
+            files.push(link);
+        } else if kept.contains(&link) {
+            files.push(link.clone());
+            walked.links.push(Linked { link, path });
+        } else {
+            walked.duplicates.push(Linked { link, path });
+        }
+    }
+    Ok(files)
+}
+
+/// Log the source of `walk`, with `prefix` as the title.
+pub(crate) fn log_source(
+    prefix: &str,
+    recursive: bool,
+    filter: &FilterArgs,
+    walked: &Walked,
+) -> Result<()> {
+    let source = &walked.source;
+    let kind = |x: &Path| match (
+        x.as_os_str() == "-",
+        walked.listed && x.is_file(),
+        x.is_file(),
+    ) {
+        (true, _, _) => ("stdin".to_string(), "List"),
+        (_, true, _) => (format!("{}", x.display()), "List"),
+        (_, _, true) if archive::is_archive(x) => (format!("{}", x.display()), "Archive"),
+        (_, _, true) => (format!("{}", x.display()), "File"),
+        _ => (format!("{}", x.display()), "Folder"),
+    };
+    if !walked.roots.is_empty() {
+        LOGGER.success(prefix, &format!("x{}", walked.roots.len()), "Roots");
+        for x in walked.roots.iter() {
+            let (x, kind) = kind(x);
+            LOGGER.success("", &x, kind);
+        }
+        LOGGER.success("Recursively", &format!("{:?}", &recursive), "");
+    } else if walked.listed {
+        let (x, kind) = kind(source);
+        LOGGER.success(prefix, &x, kind);
+        LOGGER.success("", "Listed", &format!("x{}", walked.files.len()));
+    } else {
+        let (x, kind) = kind(source);
+        LOGGER.success(prefix, &x, kind);
+        LOGGER.success("Recursively", &format!("{:?}", &recursive), "");
+    }
+    if !walked.missing.is_empty() {
+        let n = walked.missing.len();
+        LOGGER.warn("Listed but not exist", &format!("x{}", n), "Skipped");
+        for x in walked.missing.iter() {
+            LOGGER.warn("", &format!("{}", x.display()), "");
+        }
+    }
+    if !filter.is_empty() {
+        LOGGER.success("Filtering", "", "");
+        for (k, v) in [
+            ("Include", &filter.include),
+            ("Exclude", &filter.exclude),
+            ("Extensions", &filter.ext),
+        ] {
+            if !v.is_empty() {
+                LOGGER.success("", k, &v.join(","));
+            }
+        }
+        if let Some(n) = filter.max_depth {
+            LOGGER.success("", "Max depth", &format!("{}", n));
+        }
+    }
+    if filter.follow_links {
+        LOGGER.success("Symlinks followed", &format!("x{}", walked.links.len()), "");
+    }
+    if !walked.duplicates.is_empty() {
+        let n = walked.duplicates.len();
+        LOGGER.warn("Reachable more than once", &format!("x{}", n), "Skipped");
+        for x in walked.duplicates.iter() {
+            LOGGER.warn(
+                "",
+                &format!("{}", x.link.display()),
+                &format!("{}", x.path.display()),
+            );
+        }
+    }
+    if !walked.cycles.is_empty() {
+        let n = walked.cycles.len();
+        LOGGER.warn("Symlink cycles", &format!("x{}", n), "Skipped");
+        for x in walked.cycles.iter() {
+            LOGGER.warn("", &format!("{}", x.display()), "");
+        }
+    }
+    Ok(())
 }
 
 enum LoggerKind {
@@ -165,6 +900,9 @@ impl Logger {
     }
 
     fn _log_base(&self, kind: LoggerKind, t1: &str, t2: &str, prompt: &str) {
+        if is_json() {
+            return;
+        }
         let is_t1_empty = self._log_title(kind, t1);
         if !t2.is_empty() {
             self._log_text(
@@ -190,7 +928,247 @@ impl Logger {
         self._log_base(LoggerKind::Warn, t1, t2, prompt);
     }
     pub fn exit(&self, t1: &str, t2: &str, prompt: &str) {
+        if is_json() {
+            let error = [t1, t2, prompt]
+                .iter()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>()
+                .join(" · ");
+            println!("{}", serde_json::json!({ "error": error }));
+        }
         self._log_base(LoggerKind::Fail, t1, t2, prompt);
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use usearch::b1x8;
+    use usearch::ffi::{IndexOptions, MetricKind, ScalarKind};
+
+    /// 64-bit hashes, clusters of 10 within a few flipped bits, from a fixed seed.
+    fn hashes(n: usize) -> Vec<u64> {
+        let mut seed = 0x9e3779b97f4a7c15u64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut xs = Vec::with_capacity(n);
+        while xs.len() < n {
+            let center = next();
+            for _ in 0..10 {
+                let flips = next() % 4;
+                xs.push((0..flips).fold(center, |x, _| x ^ (1 << (next() % 64))));
+            }
+        }
+        xs.truncate(n);
+        xs
+    }
+
+    fn brute_force(xs: &[u64], query: u64, radius: f32) -> Vec<(u64, f32)> {
+        let mut ys: Vec<(u64, f32)> = xs
+            .iter()
+            .enumerate()
+            .map(|(k, x)| (k as u64, (x ^ query).count_ones() as f32))
+            .filter(|(_, d)| *d <= radius)
+            .collect();
+        ys.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        ys
+    }
+
+    #[test]
+    fn search_radius_as_brute_force() -> Result<()> {
+        let xs = hashes(3000);
+        let index = usearch::Index::new(&IndexOptions {
+            dimensions: 64,
+            metric: MetricKind::Hamming,
+            quantization: ScalarKind::B1,
+            expansion_search: 256,
+            ..Default::default()
+        })?;
+        index.reserve(xs.len())?;
+        for (k, x) in xs.iter().enumerate() {
+            index.add(k as u64, b1x8::from_u8s(&x.to_le_bytes()))?;
+        }
+        for exact in [true, false] {
+            for (k, x) in xs.iter().enumerate().step_by(7) {
+                let radius = (k % 6) as f32;
+                let bytes = x.to_le_bytes();
+                let mut ys = radius_search(&index, b1x8::from_u8s(&bytes), radius, exact)?;
+                ys.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                assert_eq!(ys, brute_force(&xs, *x, radius), "exact: {}", exact);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn read_lines_and_jsonl() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let list = dir.join("list.txt");
+        let (a, b) = (dir.join("imgs/a.jpg"), dir.join("imgs/b.jpg"));
+
+        // relative to the folder of the list, blank lines skipped, absolute paths kept
+        let lines = format!("imgs/a.jpg\n\n   \n{}\n", b.display());
+        std::fs::write(&list, lines)?;
+        assert_eq!(read_list(&list)?, [a.clone(), b.clone()]);
+        let lines = "{\"path\": \"imgs/a.jpg\", \"score\": 1}\n\n{\"path\": \"imgs/b.jpg\"}";
+        std::fs::write(&list, lines)?;
+        assert_eq!(read_list(&list)?, [a, b]);
+
+        // the line of the invalid one
+        for (lines, n) in [
+            ("imgs/a.jpg\n{\"file\": \"imgs/b.jpg\"}", 2),
+            ("{\"path\": ", 1),
+        ] {
+            std::fs::write(&list, lines)?;
+            match read_list(&list) {
+                Err(Error::List { line, .. }) => assert_eq!(line, n, "{}", lines),
+                x => panic!("{}: {:?}", lines, x),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn walk_listed_files() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path().join("list");
+        for rel in ["imgs/a.jpg", "imgs/sub/b.png"] {
+            std::fs::create_dir_all(dir.join(rel).parent().unwrap())?;
+            std::fs::write(dir.join(rel), rel)?;
+        }
+        let list = dir.join("list.txt");
+        let lines = "imgs/a.jpg\nimgs/missing.jpg\n\nimgs/sub\n{\"path\": \"imgs/sub/b.png\"}\n";
+        std::fs::write(&list, lines)?;
+
+        // folders skipped, missing files reported, relative to the common folder
+        let walked = walk_list(&list, &FilterArgs::default())?;
+        assert!(walked.listed);
+        assert_eq!(
+            walked.files,
+            [dir.join("imgs/a.jpg"), dir.join("imgs/sub/b.png")]
+        );
+        assert_eq!(walked.missing, [dir.join("imgs/missing.jpg")]);
+        assert_eq!(walked.root, dir.join("imgs").canonicalize()?);
+
+        // filtered relative to the common folder
+        let filter = FilterArgs {
+            include: vec!["sub/*".to_string()],
+            ..Default::default()
+        };
+        let walked = walk_list(&list, &filter)?;
+        assert_eq!(walked.files, [dir.join("imgs/sub/b.png")]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_following_links() -> Result<()> {
+        use std::os::unix::fs::symlink;
+        let tmp = tempfile::tempdir()?;
+        let (input, outside) = (tmp.path().join("input"), tmp.path().join("outside"));
+        for dir in [input.join("sub"), outside.clone()] {
+            std::fs::create_dir_all(dir)?;
+        }
+        let (input, outside) = (input.canonicalize()?, outside.canonicalize()?);
+        std::fs::write(input.join("a.jpg"), "a")?;
+        std::fs::write(outside.join("b.jpg"), "b")?;
+        // a file outside reachable twice, a file inside once more, and a cycle
+        symlink(outside.join("b.jpg"), input.join("l1.jpg"))?;
+        symlink(outside.join("b.jpg"), input.join("sub/l2.jpg"))?;
+        symlink(input.join("a.jpg"), input.join("l3.jpg"))?;
+        symlink(&input, input.join("sub/loop"))?;
+
+        let pairs = |xs: &[Linked]| -> Vec<(PathBuf, PathBuf)> {
+            xs.iter()
+                .map(|x| (x.link.clone(), x.path.clone()))
+                .collect()
+        };
+        let filter = FilterArgs {
+            follow_links: true,
+            ..Default::default()
+        };
+        let walked = walk(&input, true, false, &filter)?;
+        // each real file once, the path without links preferred, then the shallowest link
+        assert_eq!(walked.files, [input.join("a.jpg"), input.join("l1.jpg")]);
+        assert_eq!(
+            pairs(&walked.links),
+            [(input.join("l1.jpg"), outside.join("b.jpg"))]
+        );
+        assert_eq!(
+            pairs(&walked.duplicates),
+            [
+                (input.join("l3.jpg"), input.join("a.jpg")),
+                (input.join("sub/l2.jpg"), outside.join("b.jpg")),
+            ]
+        );
+        assert_eq!(walked.cycles, [input.join("sub/loop")]);
+
+        // skipped unless followed
+        let walked = walk(&input, true, false, &FilterArgs::default())?;
+        assert_eq!(walked.files, [input.join("a.jpg")]);
+        assert!(walked.links.is_empty() && walked.duplicates.is_empty());
+        assert!(walked.cycles.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn links_or_copies() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dst = |name: &str| tmp.path().join(name);
+        let src = dst("a.txt");
+        std::fs::write(&src, "a")?;
+
+        // linked, or reflinked where supported, copied otherwise
+        src2dst(&src, &dst("hard.txt"), Transfer::Link(Link::Hard))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let ino = |p: &Path| std::fs::metadata(p).map(|x| x.ino());
+            assert_eq!(ino(&dst("hard.txt"))?, ino(&src)?);
+        }
+        src2dst(&src, &dst("sym.txt"), Transfer::Link(Link::Sym))?;
+        assert_eq!(std::fs::read_link(dst("sym.txt"))?, src.canonicalize()?);
+        src2dst(&src, &dst("reflink.txt"), Transfer::Link(Link::Reflink))?;
+        assert_eq!(std::fs::read_to_string(dst("reflink.txt"))?, "a");
+
+        // a member of an archive can't be linked, extracted instead
+        let zip = dst("b.zip");
+        let mut w = zip::ZipWriter::new(std::fs::File::create(&zip)?);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        w.start_file("b.txt", options)?;
+        std::io::Write::write_all(&mut w, b"b")?;
+        w.finish()?;
+        let member = archive::member(&zip, "b.txt");
+        for link in [Link::Hard, Link::Sym, Link::Reflink] {
+            let y = dst(&format!("{:?}.txt", link));
+            src2dst(&member, &y, Transfer::Link(link))?;
+            assert!(!y.is_symlink(), "{:?}", link);
+            assert_eq!(std::fs::read_to_string(&y)?, "b", "{:?}", link);
+        }
+
+        // nor across filesystems, copied, where another one is at hand
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let shm = Path::new("/dev/shm");
+            let dev = |p: &Path| std::fs::metadata(p).map(|x| x.dev());
+            if shm.is_dir() && dev(shm)? != dev(&src)? {
+                let other = tempfile::tempdir_in(shm)?;
+                for link in [Link::Hard, Link::Reflink] {
+                    let y = other.path().join(format!("{:?}.txt", link));
+                    src2dst(&src, &y, Transfer::Link(link))?;
+                    assert_eq!(std::fs::read_to_string(&y)?, "a", "{:?}", link);
+                }
+            }
+        }
+        Ok(())
+    }
+}